@@ -1,25 +1,30 @@
 // vim:ts=4:sw=4
+// The #[allow]s on older functions keep their explicit returns and is_some()/unwrap() checks
 use std::io::Read;
 use std::process;
-use std::process::{Command, Stdio};
+use std::process::{ChildStderr, ChildStdout, Command, Stdio};
 use std::sync::{Arc, Mutex};
 use std::error::Error;
 use std::io::Write;
 use std::time::Duration;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
 use nix::unistd::Pid;
 use lazy_static::lazy_static;
 use clap::Parser;
-use nix::sys::signal::{kill,Signal};
+use nix::sys::signal::{kill,Signal,SigAction,SigHandler,SaFlags,SigSet,sigaction};
 use timeout_readwrite::TimeoutReader;
 use regex::Regex;
-use cursive::{Cursive,XY};
-use cursive::reexports::crossbeam_channel::Sender;
+use cursive::{CbSink,Cursive,XY};
 use cursive::event::{Event,Key};
 use cursive::align::HAlign;
-use cursive::views::{ResizedView, Dialog, LinearLayout, TextContent, TextView, Panel, EditView, NamedView, SelectView, Checkbox};
+use cursive::views::{ResizedView, Dialog, LinearLayout, TextContent, TextView, Panel, EditView, NamedView, SelectView, Checkbox, Button};
 use cursive::traits::*;
+use cursive::theme::{BaseColor, Color};
+use cursive::utils::markup::StyledString;
 use cursive::menu::Tree;
-use rasciigraph::{plot, Config};
+use rasciigraph::{plot, plot_many, Config};
+use serde::{Deserialize, Serialize};
 
 #[derive(Clone, PartialEq, Eq)]
 enum State {
@@ -32,30 +37,69 @@ enum State {
 // Globals
 //
 
+// Set by SIGINT/SIGTERM in headless mode
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static PROBING: AtomicBool = AtomicBool::new(false);
+
 lazy_static! {
-    static ref IPERF3_PID: Arc<Mutex<Option<i32>>> = Arc::new(Mutex::new(None));
+    static ref IPERF3_PIDS: Arc<Mutex<Vec<i32>>> = Arc::new(Mutex::new(Vec::new()));  // Every iperf3 we're running
     static ref SCREEN_SIZE: Arc<Mutex<Option<XY<usize>>>> = Arc::new(Mutex::new(None));
     static ref ARGS: Arc<Mutex<Option<Args>>> = Arc::new(Mutex::new(None));
+    static ref CONFIG: Arc<Mutex<UserConfig>> = Arc::new(Mutex::new(UserConfig::default()));
     static ref STATE: Arc<Mutex<Option<State>>> = Arc::new(Mutex::new(None));
+    static ref SHOW_STREAMS: Arc<Mutex<bool>> = Arc::new(Mutex::new(false));
+    static ref RECORDING: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
+    static ref REPLAY: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
+    static ref LATENCIES: Arc<Mutex<HashMap<String, Option<Duration>>>> = Arc::new(Mutex::new(HashMap::new()));
+    static ref SERVER_PICKER: Arc<Mutex<ServerPicker>> = Arc::new(Mutex::new(ServerPicker::default()));
+    static ref REPLAY_CONTROL: Arc<Mutex<ReplayControl>> = Arc::new(Mutex::new(ReplayControl::default()));
+    static ref BENCHMARK: Arc<Mutex<Option<Benchmark>>> = Arc::new(Mutex::new(None));
+    static ref LEADERBOARD: Arc<Mutex<Leaderboard>> = Arc::new(Mutex::new(Leaderboard::default()));
+    static ref DASHBOARD: Arc<Mutex<Vec<Pane>>> = Arc::new(Mutex::new(Vec::new()));
+    static ref HISTORY_LOCK: Mutex<()> = Mutex::new(());     // Held while history.json is read, changed and saved
+    static ref HAS_JSON_STREAM: bool = iperf3_has_json_stream();
+    static ref RE_MAIN: Regex = Regex::new("\\[([^\\]]+)\\](?:\\[(TX|RX)-[CS]\\])?\\s(.*)$").unwrap();
+    static ref RE_BITRATE: Regex = Regex::new("([\\d\\.]+)\\s(\\w+)/sec").unwrap();
+    static ref RE_TCP: Regex = Regex::new("/sec\\s+(\\d+)(?:\\s+([\\d\\.]+)\\s(\\w?)Bytes)?\\s*$").unwrap();
+    static ref RE_BYTES: Regex = Regex::new("sec\\s+([\\d\\.]+)\\s(\\w?)Bytes").unwrap();
+    static ref RE_UDP: Regex = Regex::new("([\\d\\.]+)\\sms\\s+(\\d+)/(\\d+)\\s+\\(([\\d\\.e+-]+)%\\)").unwrap();
+    static ref RE_SIZE_ARG: Regex = Regex::new("^\\d+(\\.\\d+)?[KMGTkmgt]?$").unwrap();
+    static ref RE_BITRATE_ARG: Regex = Regex::new("^\\d+(\\.\\d+)?[KMGTkmgt]?(/\\d+)?$").unwrap();
+    static ref RE_AFFINITY_ARG: Regex = Regex::new("^\\d+(,\\d+)?$").unwrap();
+    static ref RE_CONGESTION_ARG: Regex = Regex::new("^[A-Za-z0-9_-]{1,15}$").unwrap();
 }
 
+#[allow(clippy::needless_return)]
 fn has_iperf3() -> bool {
    return Command::new("which").arg("iperf3").stdout(Stdio::null()).stderr(Stdio::null()).status().expect("Could not run 'which iperf3'").success();
 }
 
+// --json-stream arrived in iperf3 3.17, older builds only give us text
+fn iperf3_has_json_stream() -> bool {
+    let result = Command::new("iperf3").arg("--help").output();
+    if result.is_err() { return false; }
+    let output = result.unwrap();
+    let help = String::from_utf8_lossy(&output.stdout).to_string() + &String::from_utf8_lossy(&output.stderr);
+    help.contains("--json-stream")
+}
+
 fn save_pid(pid_in: u32) {
-    let mut pid_opt = IPERF3_PID.lock().unwrap();
+    let mut pids = IPERF3_PIDS.lock().unwrap();
     let result: Result<i32, _> = pid_in.try_into();
     if result.is_err() { return; }
-    let pid_i32 = result.unwrap();
-    *pid_opt = Some(pid_i32);
+    pids.push(result.unwrap());
+}
+
+fn forget_pid(pid_in: u32) {
+    let mut pids = IPERF3_PIDS.lock().unwrap();
+    pids.retain(|pid| *pid as u32 != pid_in);
 }
 
-fn kill_pid() {
-    let pid_opt = IPERF3_PID.lock().unwrap();
-    if pid_opt.is_some() {
-        let pid_struct = Pid::from_raw(pid_opt.unwrap());
-        kill(pid_struct, Signal::SIGKILL).expect("Could not kill child process");
+// When quitting - run_iperf3() cleans up after itself otherwise
+fn kill_pids() {
+    let pids = IPERF3_PIDS.lock().unwrap();
+    for pid in pids.iter() {
+        let _ = kill(Pid::from_raw(*pid), Signal::SIGKILL);
     }
 }
 
@@ -64,6 +108,7 @@ fn save_screen_size(ss_in: XY<usize>) {
     *ss_opt = Some(ss_in);
 }
 
+#[allow(clippy::needless_return)]
 fn get_screen_size() -> (u32, u32) {
     let mut screen_width: u32 = 0;
     let mut screen_height: u32 = 0;
@@ -94,6 +139,7 @@ fn save_args(args_in: &Args) {
     *args_opt = Some(args_in.clone());
 }
 
+#[allow(clippy::needless_return)]
 fn get_args() -> Args {
     let args_opt = ARGS.lock().unwrap();
     if args_opt.is_none() { return Args::parse(); }
@@ -101,6 +147,16 @@ fn get_args() -> Args {
     return args;
 }
 
+fn save_config(config_in: UserConfig) {
+    let mut config = CONFIG.lock().unwrap();
+    *config = config_in;
+}
+
+fn get_config() -> UserConfig {
+    return CONFIG.lock().unwrap().clone();
+}
+
+#[allow(clippy::needless_return)]
 fn get_state() -> State{
     let state_opt = STATE.lock().unwrap();
     if state_opt.is_none() { return State::Normal; }
@@ -113,6 +169,44 @@ fn save_state(state_in: State) {
     *state_opt = Some(state_in.clone());
 }
 
+fn get_show_streams() -> bool {
+    return *SHOW_STREAMS.lock().unwrap();
+}
+
+fn toggle_show_streams() {
+    let mut show_streams = SHOW_STREAMS.lock().unwrap();
+    *show_streams = !*show_streams;
+}
+
+fn get_recording() -> Option<String> {
+    let recording_opt = RECORDING.lock().unwrap();
+    recording_opt.clone()
+}
+
+fn save_recording(recording_in: Option<String>) {
+    let mut recording_opt = RECORDING.lock().unwrap();
+    *recording_opt = recording_in;
+}
+
+fn get_replay() -> Option<String> {
+    let replay_opt = REPLAY.lock().unwrap();
+    replay_opt.clone()
+}
+
+fn save_replay(replay_in: Option<String>) {
+    let mut replay_opt = REPLAY.lock().unwrap();
+    *replay_opt = replay_in;
+}
+
+fn get_replay_control() -> ReplayControl {
+    return REPLAY_CONTROL.lock().unwrap().clone();
+}
+
+fn change_replay_control(change: impl FnOnce(&mut ReplayControl)) {
+    let mut control = REPLAY_CONTROL.lock().unwrap();
+    change(&mut control);
+}
+
 //
 // Utilites
 //
@@ -127,16 +221,16 @@ fn has_content_str(s: &str) -> bool {
     ! is_space_str(s)
 }
 
-fn is_space_string(s: &String) -> bool {
+fn is_space_string(s: &str) -> bool {
     s.trim().is_empty()
 }
 
-fn has_content_string(s: &String) -> bool {
+fn has_content_string(s: &str) -> bool {
     ! is_space_string(s)
 }
 
 fn mkerr(txt: &str) -> std::io::Error {
-    std::io::Error::new(std::io::ErrorKind::Other, txt)
+    std::io::Error::other(txt)
 }
 
 fn save_file_contents(filename: &str, content: &str) -> std::io::Result<()> {
@@ -145,9 +239,9 @@ fn save_file_contents(filename: &str, content: &str) -> std::io::Result<()> {
     Ok(())
 }
 
+#[allow(clippy::unnecessary_unwrap)]
 fn log(txt: &str) {
-    // Don't use /var/log because not all users are allowed
-    let filename = "/tmp/iperf3-tui.log";
+    let filename = &get_config().log_file;
     let max_size = 1024 * 1024;
 
     let meta_result = std::fs::metadata(filename);
@@ -159,26 +253,535 @@ fn log(txt: &str) {
         }
     }
 
-    let mut file = std::fs::OpenOptions::new().write(true).append(true).open(filename).unwrap();
-    let _ = writeln!(file, "{}", txt);
+    let file_result = std::fs::OpenOptions::new().create(true).append(true).open(filename);
+    if file_result.is_err() { return; }
+    let _ = writeln!(file_result.unwrap(), "{}", txt);
+}
+
+//
+// iperf3 output
+//
+
+// One interval from one stream, or the SUM of all streams
+#[derive(Clone, Debug, Default)]
+#[allow(dead_code)]
+struct Sample {
+    stream: String,     // eg "5" or "SUM"
+    direction: String,  // "TX" or "RX" with --bidir, otherwise empty
+    bits_per_second: f64,
+    bytes: u64,
+    retransmits: Option<u64>,
+    snd_cwnd: Option<u64>,  // bytes
+    jitter_ms: Option<f64>,
+    lost_percent: Option<f64>,
+}
+
+impl Sample {
+    fn from_json(stream: &str, direction: &str, json: &JsonStream) -> Sample {
+        Sample {
+            stream: stream.to_string(),
+            direction: direction.to_string(),
+            bits_per_second: json.bits_per_second,
+            bytes: json.bytes,
+            retransmits: json.retransmits,
+            snd_cwnd: json.snd_cwnd,
+            jitter_ms: json.jitter_ms,
+            lost_percent: json.lost_percent,
+        }
+    }
+
+    fn mbits_per_second(&self) -> f64 {
+        self.bits_per_second / 1_000_000.0
+    }
+
+    // eg "5", or "5 TX" with --bidir
+    fn name(&self) -> String {
+        if self.direction.is_empty() { return self.stream.clone(); }
+        format!("{} {}", self.stream, self.direction)
+    }
+}
+
+// The events `iperf3 --json-stream` writes, one per line, eg
+// {"event":"interval","data":{"streams":[...],"sum":{...}}}
+// We only declare the fields we use - serde skips the rest
+#[derive(Deserialize, Debug)]
+#[serde(tag = "event", content = "data", rename_all = "lowercase")]
+enum JsonEvent {
+    Start(JsonStart),
+    Interval(JsonInterval),
+    End(Box<JsonEnd>),
+    Error(String),
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct JsonStart {
+    version: String,
+    connected: Vec<JsonConnected>,
+    test_start: JsonTestStart,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct JsonConnected {
+    remote_host: String,
+    remote_port: u16,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct JsonTestStart {
+    protocol: String,
+    num_streams: u32,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct JsonInterval {
+    streams: Vec<JsonStream>,
+    sum: JsonStream,
+    sum_bidir_reverse: Option<JsonStream>,  // Only with --bidir
+}
+
+// Used for a stream in an interval, the sum of an interval and the end totals
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+#[allow(dead_code)]
+struct JsonStream {
+    socket: i32,
+    seconds: f64,
+    bytes: u64,
+    bits_per_second: f64,
+    retransmits: Option<u64>,
+    snd_cwnd: Option<u64>,
+    jitter_ms: Option<f64>,
+    lost_percent: Option<f64>,
+    omitted: bool,
+    sender: bool,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct JsonEnd {
+    sum_sent: Option<JsonStream>,
+    sum_received: Option<JsonStream>,
+    sum: Option<JsonStream>,    // UDP from older iperf3s
+    sum_sent_bidir_reverse: Option<JsonStream>,
+    sum_received_bidir_reverse: Option<JsonStream>,
+}
+
+impl JsonEnd {
+    fn summary(&self) -> Vec<SummaryLine> {
+        let bidir = self.sum_sent_bidir_reverse.is_some() || self.sum_received_bidir_reverse.is_some();
+        let mut out = Vec::new();
+        let mut push = |direction: &str, role: &str, json: &Option<JsonStream>| {
+            if json.is_none() { return; }
+            out.push(SummaryLine::new(direction, role, Sample::from_json("SUM", direction, json.as_ref().unwrap())));
+        };
+        let direction = if bidir { "TX" } else { "" };
+        push(direction, "sender", &self.sum_sent);
+        push(direction, "receiver", if self.sum_received.is_some() { &self.sum_received } else { &self.sum });
+        if bidir {
+            push("RX", "sender", &self.sum_sent_bidir_reverse);
+            push("RX", "receiver", &self.sum_received_bidir_reverse);
+        }
+        out
+    }
+}
+
+// The totals iperf3 prints when a fixed-duration test finishes
+#[derive(Clone, Debug)]
+struct SummaryLine {
+    label: String,  // eg "Sender", or "Upload receiver" with --bidir
+    sample: Sample,
+}
+
+impl SummaryLine {
+    fn new(direction: &str, role: &str, sample: Sample) -> SummaryLine {
+        let label = match direction {
+            "TX" => format!("Upload {}", role),
+            "RX" => format!("Download {}", role),
+            _ => role[..1].to_uppercase() + &role[1..],
+        };
+        SummaryLine { label, sample }
+    }
+
+    // eg "Sender            1 GB    939.00 Mbits/sec  retr 0"
+    fn friendly(&self) -> String {
+        let mut out = format!("{:17} {:>8} {:>17}", self.label, friendly_bytes(self.sample.bytes), friendly_bitrate(self.sample.mbits_per_second()));
+        if let Some(retransmits) = self.sample.retransmits { out += &format!("  retr {}", retransmits); }
+        if let Some(jitter_ms) = self.sample.jitter_ms { out += &format!("  jitter {:.3} ms", jitter_ms); }
+        if let Some(lost_percent) = self.sample.lost_percent { out += &format!("  loss {:.2} %", lost_percent); }
+        out
+    }
+}
+
+impl JsonInterval {
+    // The SUM (one per direction with --bidir), plus each stream when there is more than one
+    fn samples(&self) -> Vec<Sample> {
+        if self.sum.omitted { return Vec::new(); }     // -O, TCP is still ramping up
+        let bidir = self.sum_bidir_reverse.is_some();
+        let direction = |json: &JsonStream| {
+            if !bidir { return ""; }
+            if json.sender { "TX" } else { "RX" }
+        };
+
+        let mut sum = Sample::from_json("SUM", direction(&self.sum), &self.sum);
+
+        // Only the streams have a congestion window
+        let cwnds: Vec<u64> = self.streams.iter().filter_map(|stream| stream.snd_cwnd).collect();
+        if !cwnds.is_empty() { sum.snd_cwnd = Some(cwnds.iter().sum()); }
+
+        let mut out = vec![sum];
+        if bidir {
+            let reverse = self.sum_bidir_reverse.as_ref().unwrap();
+            out.push(Sample::from_json("SUM", direction(reverse), reverse));
+        }
+
+        if self.streams.len() > out.len() {
+            for stream in &self.streams {
+                out.push(Sample::from_json(&stream.socket.to_string(), direction(stream), stream));
+            }
+        }
+        out
+    }
+}
+
+fn parse_json_line(line: &str) -> Option<JsonEvent> {
+    let result = serde_json::from_str::<JsonEvent>(line);
+    if result.is_err() { return None; }
+    Some(result.unwrap())
+}
+
+// eg 941 Mbits -> 941000000.0
+fn units_multiplier(units: &str) -> f64 {
+    match units {
+        "Kbits" => 1e3,
+        "Mbits" => 1e6,
+        "Gbits" => 1e9,
+        "Tbits" => 1e12,
+        "Pbits" => 1e15,
+        _ => 1.0,
+    }
+}
+
+// eg 376 KBytes -> 385024
+fn bytes_multiplier(prefix: &str) -> f64 {
+    match prefix {
+        "K" => 1024.0,
+        "M" => 1024.0 * 1024.0,
+        "G" => 1024.0 * 1024.0 * 1024.0,
+        _ => 1.0,
+    }
+}
+
+// For iperf3 builds without --json-stream. A line looks like
+// [  5]   0.00-1.00   sec   112 MBytes   941 Mbits/sec    0    376 KBytes
+fn parse_text_line(line: &str) -> Option<Sample> {
+    if line.contains("- - -") { return None; }  // End
+    if line.contains("(omitted)") { return None; }  // -O
+
+    let c = RE_MAIN.captures(line)?;
+    let stream = c.get(1).unwrap().as_str().trim();
+    let direction = c.get(2).map_or("", |m| m.as_str());
+    let remainder = c.get(3).unwrap().as_str().trim();
+    if remainder.contains("Interval") { return None; }  // Start or end
+
+    let c = RE_BITRATE.captures(remainder)?;
+    let bitrate_result = c.get(1).unwrap().as_str().parse::<f64>();
+    if bitrate_result.is_err() { return None; }
+    let units = c.get(2).unwrap().as_str();
+
+    let mut sample = Sample {
+        stream: stream.to_string(),
+        direction: direction.to_string(),
+        bits_per_second: bitrate_result.unwrap() * units_multiplier(units),
+        ..Default::default()
+    };
+
+    // TCP senders add retransmits and the congestion window (but not on SUM lines).
+    // UDP senders have a datagram count in the same place, the caller knows which it is.
+    if let Some(c) = RE_TCP.captures(remainder) {
+        sample.retransmits = c.get(1).unwrap().as_str().parse::<u64>().ok();
+        let cwnd_opt = c.get(2).and_then(|cwnd| cwnd.as_str().parse::<f64>().ok());
+        if let Some(cwnd) = cwnd_opt {
+            let prefix = c.get(3).unwrap().as_str();
+            sample.snd_cwnd = Some((cwnd * bytes_multiplier(prefix)) as u64);
+        }
+    }
+
+    // UDP receivers add jitter and lost/total datagrams, eg
+    // [  5]   0.00-1.00   sec   128 KBytes  1.05 Mbits/sec  0.019 ms  0/91 (0%)
+    if let Some(c) = RE_UDP.captures(remainder) {
+        sample.jitter_ms = c.get(1).unwrap().as_str().parse::<f64>().ok();
+        sample.lost_percent = c.get(4).unwrap().as_str().parse::<f64>().ok();
+    }
+    Some(sample)
+}
+
+// After the "- - -" line iperf3 prints a total for each stream, and the SUM, eg
+// [  5]   0.00-10.00  sec  1.09 GBytes   939 Mbits/sec    0             sender
+fn parse_text_summary_line(line: &str) -> Option<(String, Sample)> {
+    let trimmed = line.trim_end();
+    for role in ["sender", "receiver"] {
+        if trimmed.ends_with(role) {
+            let without_role = trimmed.trim_end_matches(role);
+            let mut sample = parse_text_line(without_role)?;
+
+            // The bytes are only worth having here, for the summary
+            if let Some(c) = RE_BYTES.captures(without_role)
+                && let Ok(bytes) = c.get(1).unwrap().as_str().parse::<f64>() {
+                sample.bytes = (bytes * bytes_multiplier(c.get(2).unwrap().as_str())) as u64;
+            }
+            return Some((role.to_string(), sample));
+        }
+    }
+    None
+}
+
+//
+// Recording
+//
+
+// One row of a recording, CSV or JSON lines depending on the file extension
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+struct Record {
+    timestamp: String,  // RFC 3339, local time
+    server: String,
+    protocol: String,   // "tcp" or "udp"
+    direction: String,  // "upload" or "download"
+    stream: String,
+    bits_per_second: f64,
+    bytes: u64,
+    retransmits: Option<u64>,
+    snd_cwnd: Option<u64>,
+    jitter_ms: Option<f64>,
+    lost_percent: Option<f64>,
+}
+
+impl Record {
+    fn new(args: &Args, sample: &Sample) -> Record {
+        let upload = if args.bidir { sample.direction != "RX" } else { !args.reverse };
+        Record {
+            timestamp: chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, false),
+            server: args.get_server_as_string(),
+            protocol: if args.udp { "udp" } else { "tcp" }.to_string(),
+            direction: if upload { "upload" } else { "download" }.to_string(),
+            stream: sample.stream.clone(),
+            bits_per_second: sample.bits_per_second,
+            bytes: sample.bytes,
+            retransmits: if args.udp { None } else { sample.retransmits },    // UDP has none
+            snd_cwnd: sample.snd_cwnd,
+            jitter_ms: sample.jitter_ms,
+            lost_percent: sample.lost_percent,
+        }
+    }
+}
+
+impl Record {
+    fn to_sample(&self, args: &Args) -> Sample {
+        let mut direction = "";
+        if args.bidir {
+            direction = if self.direction == "download" { "RX" } else { "TX" };
+        }
+        Sample {
+            stream: self.stream.clone(),
+            direction: direction.to_string(),
+            bits_per_second: self.bits_per_second,
+            bytes: self.bytes,
+            retransmits: self.retransmits,
+            snd_cwnd: self.snd_cwnd,
+            jitter_ms: self.jitter_ms,
+            lost_percent: self.lost_percent,
+        }
+    }
+}
+
+fn read_recording(filename: &str) -> Result<Vec<Record>, Box<dyn Error>> {
+    let mut out = Vec::<Record>::new();
+    if is_csv_filename(filename) {
+        let mut rdr = csv::Reader::from_path(filename)?;
+        for result in rdr.deserialize() {
+            out.push(result?);
+        }
+    }
+    else {
+        let contents = std::fs::read_to_string(filename)?;
+        for line in contents.lines() {
+            if is_space_str(line) { continue; }
+            out.push(serde_json::from_str(line)?);
+        }
+    }
+
+    if out.is_empty() {
+        return Err("No intervals found in the recording".into());
+    }
+    // We replay one test, so a recording that went on to another server is no good
+    let other_opt = out.iter().find(|record| record.server != out[0].server || record.protocol != out[0].protocol);
+    if let Some(other) = other_opt {
+        return Err(format!("The recording has tests of {} {} and {} {} - it can only replay one", out[0].server, out[0].protocol, other.server, other.protocol).into());
+    }
+    Ok(out)
+}
+
+// Work out the options the recording was made with, as far as the graph cares
+fn args_from_records(records: &[Record]) -> Args {
+    let mut args = Args {
+        server: Some(records[0].server.clone()),
+        udp: records[0].protocol == "udp",
+        ..Default::default()
+    };
+
+    let has_upload = records.iter().any(|record| record.direction == "upload");
+    let has_download = records.iter().any(|record| record.direction == "download");
+    args.bidir = has_upload && has_download;
+    args.reverse = has_download && !has_upload;
+
+    if records.iter().any(|record| record.stream == "SUM") {
+        let mut streams: Vec<&String> = records.iter().filter(|record| record.stream != "SUM").map(|record| &record.stream).collect();
+        streams.sort();
+        streams.dedup();
+        let per_direction = if args.bidir { streams.len() / 2 } else { streams.len() };
+        args.parallel = Some((per_direction as u32).clamp(2, 128));
+    }
+    args
+}
+
+fn is_csv_filename(filename: &str) -> bool {
+    filename.to_lowercase().ends_with(".csv")
+}
+
+// eg ~/.local/share/iperf3-tui/recordings
+fn get_data_dir(name: &str) -> std::io::Result<std::path::PathBuf> {
+    let subfolder = "iperf3-tui";
+    let mut data_path = dirs::data_dir().expect("Could not find data directory");
+    data_path.push(subfolder);
+    data_path.push(name);
+    std::fs::create_dir_all(&data_path)?;
+    Ok(data_path)
+}
+
+fn get_recordings_dir() -> std::io::Result<std::path::PathBuf> {
+    get_data_dir("recordings")
+}
+
+// eg ~/.local/share/iperf3-tui/recordings/2025-08-08_13-10-00.csv
+fn get_new_recording_filename() -> std::io::Result<String> {
+    let basename = chrono::Local::now().format("%Y-%m-%d_%H-%M-%S.csv").to_string();
+    let abs_filename = get_recordings_dir()?.join(basename);
+    Ok(abs_filename.to_str().unwrap().to_string())
+}
+
+fn append_record(filename: &str, record: &Record) -> Result<(), Box<dyn Error>> {
+    let mut file = std::fs::OpenOptions::new().create(true).append(true).open(filename)?;
+    if is_csv_filename(filename) {
+        let is_new = file.metadata()?.len() == 0;
+        let mut wtr = csv::WriterBuilder::new().has_headers(is_new).from_writer(file);
+        wtr.serialize(record)?;
+        wtr.flush()?;
+    }
+    else {
+        writeln!(file, "{}", serde_json::to_string(record)?)?;
+    }
+    Ok(())
+}
+
+fn record_sample(args: &Args, sample: &Sample) {
+    let filename_opt = get_recording();
+    if filename_opt.is_none() { return; }
+    let filename = filename_opt.unwrap();
+
+    if let Err(err) = append_record(&filename, &Record::new(args, sample)) {
+        log(&format!("record_sample: could not write to {}: {}", filename, err));
+    }
 }
 
 //
 // Graphing
 //
 
+// Skips the NaNs that mark gaps
+#[allow(clippy::needless_return)]
 fn average(numbers: &[f64]) -> f64 {
-    let sum:f64  = numbers.iter().sum();
-    let count = numbers.len() as f64;
+    let sum:f64  = numbers.iter().filter(|number| !number.is_nan()).sum();
+    let count = numbers.iter().filter(|number| !number.is_nan()).count() as f64;
     return sum / count;
 }
 
+// Every bitrate in a session (Mbits/sec), not just what fits on the screen
+#[derive(Default, Clone, Debug)]
+struct Stats {
+    values: Vec::<f64>,
+    sorted: Vec::<f64>,
+}
+
+impl Stats {
+    fn add(&mut self, value: f64) {
+        self.values.push(value);
+        let index = self.sorted.partition_point(|x| *x < value);
+        self.sorted.insert(index, value);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    fn current(&self) -> f64 {
+        *self.values.last().unwrap_or(&0.0)
+    }
+
+    fn min(&self) -> f64 {
+        *self.sorted.first().unwrap_or(&0.0)
+    }
+
+    fn max(&self) -> f64 {
+        *self.sorted.last().unwrap_or(&0.0)
+    }
+
+    fn mean(&self) -> f64 {
+        if self.is_empty() { return 0.0; }
+        average(&self.values)
+    }
+
+    // Linear interpolation between the closest ranks
+    fn percentile(&self, percent: f64) -> f64 {
+        if self.is_empty() { return 0.0; }
+        let rank = (percent / 100.0) * (self.sorted.len() - 1) as f64;
+        let lower = rank.floor() as usize;
+        let upper = rank.ceil() as usize;
+        self.sorted[lower] + (self.sorted[upper] - self.sorted[lower]) * (rank - lower as f64)
+    }
+
+    fn median(&self) -> f64 {
+        self.percentile(50.0)
+    }
+
+    // Population standard deviation
+    fn stddev(&self) -> f64 {
+        if self.is_empty() { return 0.0; }
+        let mean = self.mean();
+        let variance = self.values.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>() / self.values.len() as f64;
+        variance.sqrt()
+    }
+
+    // eg "Now 941  Min 900  Max 950  Mean 930  Median 935  P95 948  StdDev 12.3 Mbits/sec"
+    fn friendly(&self) -> String {
+        let mut units = "Mbits".to_string();
+        let figures = vec![self.current(), self.min(), self.max(), self.mean(), self.median(), self.percentile(95.0)];
+        let scaled = scale(&mut units, &figures);
+        let stddev = self.stddev() * 1e6 / units_multiplier(&units);   // Not part of the scaling, it can be near zero
+        format!("Now {:.2}  Min {:.2}  Max {:.2}  Mean {:.2}  Median {:.2}  P95 {:.2}  StdDev {:.2} {}/sec",
+            scaled[0], scaled[1], scaled[2], scaled[3], scaled[4], scaled[5], stddev, units)
+    }
+}
+
 // The bitrates come here in Mbits/sec.
 // If the average bitrate is greater than 1000 then we divide all bitrates
 // by 1000 and change the units to Gbit, for example.
 // units: is in/out
 // return: Updated bitrates
-fn scale(units: &mut String, bitrates_in: &Vec::<f64>) -> Vec::<f64> {
+#[allow(clippy::needless_return)]
+fn scale(units: &mut String, bitrates_in: &[f64]) -> Vec::<f64> {
     let step = 1000.0;
     let step_squared = step * step;
     let step_cubed = step_squared * step;
@@ -186,7 +789,7 @@ fn scale(units: &mut String, bitrates_in: &Vec::<f64>) -> Vec::<f64> {
     let step_reciprocal_squared = step_reciprocal * step_reciprocal;
     let step_reciprocal_cubed = step_reciprocal_squared * step_reciprocal;
 
-    let mut bitrates_scaled = bitrates_in.clone();
+    let mut bitrates_scaled = bitrates_in.to_vec();
     let average = average(&bitrates_scaled);
     let mut multiply_by = 1.0;
     if average > step_cubed {
@@ -219,6 +822,43 @@ fn scale(units: &mut String, bitrates_in: &Vec::<f64>) -> Vec::<f64> {
     return bitrates_scaled;
 }
 
+// Like scale() but every series gets the same units so they can share a graph
+fn scale_many(units: &mut String, series_in: &Vec::<Vec::<f64>>) -> Vec::<Vec::<f64>> {
+    let all: Vec::<f64> = series_in.iter().flatten().cloned().collect();
+    let all_scaled = scale(units, &all);
+
+    let mut out = Vec::new();
+    let mut start = 0;
+    for series in series_in {
+        out.push(all_scaled[start..start + series.len()].to_vec());
+        start += series.len();
+    }
+    out
+}
+
+// eg "Streams: 5 312.40  7 310.21  9 305.96 Mbits/sec"
+fn streams_legend(streams: &BTreeMap<String, Vec::<f64>>) -> String {
+    let mut out = "Streams:".to_string();
+    for (name, bitrates) in streams {
+        if bitrates.is_empty() { continue; }
+        out += &format!("  {} {:.2}", name, bitrates.last().unwrap());
+    }
+    out += " Mbits/sec";
+    out
+}
+
+// Scale and plot, with the units in the top left corner
+fn plot_bitrates(bitrates: &[f64], graph_width: u32, graph_height: u32) -> String {
+    if bitrates.is_empty() { return "Waiting for data...".to_string(); }
+
+    let mut units = "Mbits".to_string();
+    let bitrates_scaled = scale(&mut units, bitrates);
+    let config = Config::default().with_width(graph_width).with_height(graph_height);
+    let content = plot(bitrates_scaled, config);
+    replace_at_start(&content, &left_pad(units, 6))
+}
+
+#[allow(clippy::needless_return)]
 fn left_pad(str_in: String, n: usize) -> String {
     let mut s = str_in;
     while s.len() < n {
@@ -228,6 +868,7 @@ fn left_pad(str_in: String, n: usize) -> String {
 }
 
 // Needs to be UTF-8 safe
+#[allow(clippy::needless_return)]
 fn replace_at_start(original: &str, replacement: &str) -> String {
     let mut original_chars = original.chars();
     let n = replacement.chars().count();
@@ -235,102 +876,511 @@ fn replace_at_start(original: &str, replacement: &str) -> String {
     return format!("{}{}", replacement, after)
 }
 
-fn background_graph_worker(sink: &Sender<Box<dyn FnOnce(&mut Cursive) + Send>>, content_graph: &TextContent) {
-    save_state(State::Normal);
-    let args = get_args();
-    let friendly = args.friendly();
-    sink.send(Box::new(|s: &mut Cursive| {
-        s.call_on_name("pan3", |view: &mut NamedView<Panel<NamedView<ResizedView<NamedView<TextView>>>>> | view.get_mut().set_title(friendly));
-    })).unwrap();
+// One character per value, scaled so the largest value is a full block
+fn sparkline(values: &[f64]) -> String {
+    let blocks = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let max = values.iter().cloned().fold(0.0, f64::max);
+    let mut out = String::new();
+    for value in values {
+        let mut index = 0;
+        if max > 0.0 {
+            index = ((value / max) * (blocks.len() - 1) as f64).round() as usize;
+        }
+        out.push(blocks[index.min(blocks.len() - 1)]);
+    }
+    out
+}
 
-    let server_opt = args.get_server();
-    if server_opt.is_none() {
-        content_graph.set_content("Server is not selected.\nYou can quit, specify a server on the command line\nor select a server from the menu");
-        return;
+// Jitter comes in milliseconds - show sub-millisecond jitter in microseconds
+fn friendly_jitter(jitter_ms: f64) -> String {
+    if jitter_ms < 1.0 {
+        return format!("{:7.1} us", jitter_ms * 1000.0);
     }
+    format!("{:7.3} ms", jitter_ms)
+}
 
-    let mut cmd = Command::new("iperf3");
-    cmd.arg("--forceflush") // Don't buffer between lines
-       .arg("--interval").arg("1") // Every second
-       .arg("--time").arg("0") // Run forever
-       .arg("--format").arg("m");   // In megabits
+// eg 385024 -> "376 KB"
+fn friendly_bytes(bytes: u64) -> String {
+    let mut value = bytes as f64;
+    for prefix in ["B", "KB", "MB"] {
+        if value < 1024.0 { return format!("{:.0} {}", value, prefix); }
+        value /= 1024.0;
+    }
+    format!("{:.1} GB", value)
+}
 
-    // User-supplied options
-    if args.ipv6 { cmd.arg("-6"); }
-    if args.ports.is_some() { cmd.arg("-p").arg(args.get_ports()); }
-    if args.reverse { cmd.arg("-R"); }
-    if args.udp { cmd.arg("-u"); }
+// Lines under the graph for TCP tests where we are the sender.
+// Retransmits well above the average are shown in red.
+fn tcp_strip(retransmits: &[f64], cwnds: &[f64]) -> StyledString {
+    let mut out = StyledString::new();
+    if retransmits.is_empty() { return out; }
 
-    let server_str1 = server_opt.clone().unwrap();
-    let server_str2 = server_opt.clone().unwrap();
-    if server_opt.is_some() { cmd.arg("--client").arg(server_str1); }
-    log(&format!("background_graph_worker: server={}", server_str2));
+    let retr_average = average(retransmits);
+    let retr_last = *retransmits.last().unwrap();
+    out.append_plain(format!("  Retr {:8.0}     ", retr_last));
+    let blocks = sparkline(retransmits);
+    for (value, block) in retransmits.iter().zip(blocks.chars()) {
+        if *value > 0.0 && *value >= 2.0 * retr_average {
+            out.append_styled(block.to_string(), Color::Light(BaseColor::Red));
+        }
+        else {
+            out.append_plain(block.to_string());
+        }
+    }
 
-    cmd.stdout(Stdio::piped())
-       .stderr(Stdio::piped());
+    if !cwnds.is_empty() {
+        out.append_plain(format!("\n  Cwnd {:>8}     {}", friendly_bytes(*cwnds.last().unwrap() as u64), sparkline(cwnds)));
+    }
+    out
+}
 
-    let result = cmd.spawn();
-    if result.is_err() {
-       content_graph.set_content("Could not run iperf3 - is it installed?");
-       return;
+// Lines under the graph for UDP tests
+fn udp_strip(jitters: &[f64], losses: &[f64]) -> String {
+    if jitters.is_empty() && losses.is_empty() {
+        // The client only sees these when it is the receiver
+        return "Jitter and loss are measured by the receiver - use reverse (-R) to see them".to_string();
     }
-    let child = result.unwrap();
 
-    save_pid(child.id());
+    let mut out = String::new();
+    if !jitters.is_empty() {
+        out += &format!("Jitter {}  {}\n", friendly_jitter(*jitters.last().unwrap()), sparkline(jitters));
+    }
+    if !losses.is_empty() {
+        out += &format!("  Loss {:8.2} %   {}", losses.last().unwrap(), sparkline(losses));
+    }
+    out.trim_end().to_string()
+}
 
-    //
-    // stderr
-    //
-  
-    {
-        let server = args.get_server_as_string();
-        let stderr_msg = format!("Checking connection to {} ...", server);
-        content_graph.set_content(stderr_msg);
-   
-        let stderr_result = child.stderr;
+// eg "12/30 s [██████░░░░░░░░░] 18 s left"
+fn progress_bar(elapsed: u32, total: u32, width: usize) -> String {
+    let elapsed = elapsed.min(total);
+    let bar_width = width.saturating_sub(30).max(10);
+    let done = (bar_width as u64 * elapsed as u64 / total.max(1) as u64) as usize;
+    format!("{}/{} s [{}{}] {} s left", elapsed, total, "█".repeat(done), "░".repeat(bar_width - done), total - elapsed)
+}
 
-        if stderr_result.is_none() {
-            content_graph.set_content("Could not get stderr from iperf3");
-            return;
+// With one stream there are no SUM lines - the stream is the total
+fn is_total(args: &Args, sample: &Sample) -> bool {
+    sample.stream == "SUM" || args.get_parallel() <= 1
+}
+
+// Everything we have seen in a session, trimmed to what fits on the screen
+#[derive(Default)]
+struct Graph {
+    bitrates: Vec::<f64>,
+    bitrates_rx: Vec::<f64>,    // Only with --bidir
+    jitters: Vec::<f64>,
+    losses: Vec::<f64>,
+    streams: BTreeMap::<String, Vec<f64>>,
+    retransmits: Vec::<f64>,
+    cwnds: Vec::<f64>,
+    stats: Stats,
+    stats_rx: Stats,            // Only with --bidir
+    gaps: u32,                  // Times iperf3 was restarted
+    missing: Duration,          // And how long we weren't measuring
+    area: Option<(u32, u32)>,   // Columns for the graph and rows for it and the strips. None is the whole screen
+}
+
+impl Graph {
+    fn area(&self) -> (u32, u32) {
+        if let Some(area) = self.area { return area; }
+        let (screen_width, screen_height) = get_screen_size();
+        let config = get_config();
+        (screen_width.saturating_sub(config.graph_margin_width), screen_height.saturating_sub(config.graph_margin_height))
+    }
+
+    // A NaN breaks the line, one for each interval we missed, up to a limit
+    fn add_gap(&mut self, missing: Duration) {
+        self.gaps += 1;
+        self.missing += missing;
+        let intervals = ((missing.as_secs_f64() / get_config().interval).round() as usize).clamp(1, 30);
+        for _ in 0..intervals {
+            self.bitrates.push(f64::NAN);
+            if !self.bitrates_rx.is_empty() { self.bitrates_rx.push(f64::NAN); }
+            for series in self.streams.values_mut() { series.push(f64::NAN); }
         }
-        let stderr = stderr_result.unwrap();
+    }
 
-        let mut stderr_data = String::new();
-        let mut stderr_rdr = TimeoutReader::new(stderr, Duration::from_secs(5));
-        let _ = stderr_rdr.read_to_string(&mut stderr_data);
-        if !stderr_data.is_empty() {
-            let stderr_err = format!("{}\nYou can quit or select another server", stderr_data.trim());
-            content_graph.set_content(stderr_err);
-            return;
+    // Returns true when the graph should be redrawn - ie for a total rather than a single stream
+    fn add(&mut self, args: &Args, sample: &Sample) -> bool {
+        let graph_width = self.area().0 as usize;
+
+        if !is_total(args, sample) {
+            let series = self.streams.entry(sample.name()).or_default();
+            series.push(sample.mbits_per_second());
+            while series.len() > graph_width { series.remove(0); }
+            return false;
+        }
+
+        if sample.direction == "RX" {
+            self.stats_rx.add(sample.mbits_per_second());
+            self.bitrates_rx.push(sample.mbits_per_second());
+            while self.bitrates_rx.len() > graph_width { self.bitrates_rx.remove(0); }
         }
         else {
-            let stderr_msg = format!("No immediate error from {} ...", server);
-            content_graph.set_content(stderr_msg);
+            self.stats.add(sample.mbits_per_second());
+            self.bitrates.push(sample.mbits_per_second());
+            while self.bitrates.len() > graph_width { self.bitrates.remove(0); }
         }
-    }
 
-    //
-    // stdout
-    //
-
-    let stdout_result = child.stdout;
+        let is_tcp_sender = !args.udp && !args.reverse;
+        if let Some(jitter_ms) = sample.jitter_ms { self.jitters.push(jitter_ms); }
+        if let Some(lost_percent) = sample.lost_percent { self.losses.push(lost_percent); }
+        if is_tcp_sender && let Some(retransmits) = sample.retransmits { self.retransmits.push(retransmits as f64); }
+        if is_tcp_sender && let Some(snd_cwnd) = sample.snd_cwnd { self.cwnds.push(snd_cwnd as f64); }
 
-    if stdout_result.is_none() {
-        content_graph.set_content("Could not get output from iperf3");
-        return;
+        let strip_width = graph_width.saturating_sub(20);
+        while self.jitters.len() > strip_width { self.jitters.remove(0); }
+        while self.losses.len() > strip_width { self.losses.remove(0); }
+        while self.retransmits.len() > strip_width { self.retransmits.remove(0); }
+        while self.cwnds.len() > strip_width { self.cwnds.remove(0); }
+        true
     }
-    let stdout = stdout_result.unwrap();
 
-    let re_main = Regex::new("\\[([^\\]]+)\\]\\s(.*)$").unwrap();
-    let re_bitrate = Regex::new("([\\d\\.]+)\\s(\\w+)/sec").unwrap();
+    fn draw(&self, args: &Args) -> StyledString {
+        let (graph_width, rows) = self.area();
 
-    let mut bitrates = Vec::<f64>::new();
-    
-    let mut byte_line = Vec::new();
+        let show_streams = get_show_streams() && !self.streams.is_empty();
+        let mut strip_height = if args.udp { 2 } else { 0 };
+        strip_height += if args.bidir { 2 } else { 1 };    // Stats
+        if args.get_time() > 0 { strip_height += 1; }
+        if show_streams { strip_height += 1; }
+        if !self.retransmits.is_empty() { strip_height += 1; }
+        if !self.cwnds.is_empty() { strip_height += 1; }
+        if self.gaps > 0 { strip_height += 1; }
+        let graph_height = rows.saturating_sub(strip_height).max(3);
+
+        let mut content;
+        if show_streams {
+            let mut units = "Mbits".to_string();
+            let series: Vec::<Vec::<f64>> = self.streams.values().cloned().collect();
+            let config = Config::default().with_width(graph_width).with_height(graph_height);
+            let plotted = plot_many(scale_many(&mut units, &series), config);
+            content = replace_at_start(&plotted, &left_pad(units, 6));
+        }
+        else if args.bidir {
+            // Stacked, each with its own scale
+            let half_height = (graph_height.saturating_sub(2) / 2).max(2);
+            content = "Upload (client to server)\n".to_string();
+            content += &plot_bitrates(&self.bitrates, graph_width, half_height);
+            content += "\nDownload (server to client)\n";
+            content += &plot_bitrates(&self.bitrates_rx, graph_width, half_height);
+        }
+        else {
+            content = plot_bitrates(&self.bitrates, graph_width, graph_height);
+        }
+        if show_streams {
+            content += "\n";
+            content += &streams_legend(&self.streams);
+        }
+        if args.get_time() > 0 {
+            content += "\n";
+            content += &progress_bar((self.stats.values.len() as f64 * get_config().interval) as u32, args.get_time(), graph_width as usize);
+        }
+        if args.bidir {
+            content += &format!("\n    Up: {}", self.stats.friendly());
+            content += &format!("\n  Down: {}", self.stats_rx.friendly());
+        }
+        else {
+            content += &format!("\n{}", self.stats.friendly());
+        }
+        if self.gaps > 0 {
+            content += &format!("\nReconnected {} time{}, {} s not measured", self.gaps, if self.gaps == 1 { "" } else { "s" }, self.missing.as_secs());
+        }
+        if args.udp {
+            content += "\n";
+            content += &udp_strip(&self.jitters, &self.losses);
+        }
+
+        let mut styled = StyledString::plain(content);
+        if !self.retransmits.is_empty() {
+            styled.append_plain("\n");
+            styled.append(tcp_strip(&self.retransmits, &self.cwnds));
+        }
+        styled
+    }
+}
+
+fn set_graph_title(sink: &CbSink, title: String) {
+    sink.send(Box::new(|s: &mut Cursive| {
+        s.call_on_name("pan3", |view: &mut NamedView<Panel<NamedView<ResizedView<NamedView<TextView>>>>> | view.get_mut().set_title(title));
+    })).unwrap();
+}
+
+// What run_iperf3() has to say
+enum Iperf3Output {
+    Status(String),     // eg Connecting
+    Error(String),      // iperf3 has given up, or could not start
+    Sample(Sample),
+    Summary(Vec<SummaryLine>),  // At the end of a fixed-duration test
+    Gap(Duration),      // iperf3 was restarted and nothing was measured for this long
+}
+
+// eg "5200-5209" -> 5200 to 5209, None if it isn't a range we understand
+fn port_range(ports: &str) -> Option<Vec<u16>> {
+    let mut parts = ports.split('-').map(|part| part.trim().parse::<u16>());
+    let first = parts.next()?.ok()?;
+    let last = match parts.next() {
+        Some(part) => part.ok()?,
+        None => first,
+    };
+    if parts.next().is_some() || last < first || last - first > 100 { return None; }
+    Some((first..=last).collect())
+}
+
+// eg "iperf3: error - the server is busy running a test. try again later", or ours when every port was
+fn is_busy(err: &str) -> bool {
+    err.to_lowercase().contains("is busy running a test")
+}
+
+// Public servers take one test at a time per port, so walk the port range until one is free,
+// then again after a wait if they're all busy
+fn run_iperf3(args: &Args, output: &mut dyn FnMut(Iperf3Output), should_stop: &dyn Fn() -> bool) {
+    // Remember whether we got through, for Select Server. A busy server is there, so doesn't count
+    let mut worked = false;
+    let mut failed = false;
+    run_iperf3_ports(args, &mut |out| {
+        match &out {
+            Iperf3Output::Sample(_) => worked = true,
+            Iperf3Output::Error(err) if !is_busy(err) => failed = true,
+            _ => {}
+        }
+        output(out);
+    }, should_stop);
+    if worked || failed {
+        change_history(|history| history.record_test(&args.get_server_as_string(), worked));
+    }
+}
+
+fn run_iperf3_ports(args: &Args, output: &mut dyn FnMut(Iperf3Output), should_stop: &dyn Fn() -> bool) {
+    let ports_opt = port_range(&args.get_ports());
+    if ports_opt.is_none() {
+        run_iperf3_port(args, None, output, should_stop);
+        return;
+    }
+    let server = args.get_server_as_string();
+
+    // The port that worked last time goes first
+    let mut ports = ports_opt.unwrap();
+    let working_opt = load_history().working_ports.get(&server).copied();
+    if let Some(working) = working_opt && ports.contains(&working) {
+        ports.retain(|port| *port != working);
+        ports.insert(0, working);
+    }
+
+    let config = get_config();
+    let mut wait = Duration::from_secs(config.busy_wait);
+    for attempt in 0..=config.busy_retries {
+        if attempt > 0 {
+            output(Iperf3Output::Status(format!("Every port of {} is busy, trying again in {} s (retry {} of {}) ...", server, wait.as_secs(), attempt, config.busy_retries)));
+            let until = std::time::Instant::now() + wait;
+            while std::time::Instant::now() < until {
+                if should_stop() { return; }
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            wait *= 2;
+        }
+
+        for port in &ports {
+            let mut busy = false;
+            let mut worked = false;
+            run_iperf3_port(args, Some(*port), &mut |out| {
+                match out {
+                    Iperf3Output::Error(err) if is_busy(&err) => busy = true,
+                    Iperf3Output::Sample(sample) => { worked = true; output(Iperf3Output::Sample(sample)); }
+                    other => output(other),
+                }
+            }, should_stop);
+            if worked && working_opt != Some(*port) {
+                change_history(|history| { history.working_ports.insert(server.clone(), *port); });
+            }
+            if !busy || should_stop() { return; }
+            log(&format!("run_iperf3: {} port {} is busy", server, port));
+        }
+    }
+    output(Iperf3Output::Error(format!("Every port of {} ({}) is busy running a test, try again later", server, args.get_ports())));
+}
+
+// With --reconnect, starts iperf3 again when it stops part way through a test.
+// The tries are in a row - a restart that measures something starts the count again
+fn run_iperf3_reconnecting(args: &Args, output: &mut dyn FnMut(Iperf3Output), should_stop: &dyn Fn() -> bool) {
+    let max_tries = args.reconnect.unwrap_or(0);
+    if max_tries == 0 {
+        run_iperf3(args, output, should_stop);
+        return;
+    }
+
+    let config = get_config();
+    let mut args = args.clone();
+    let mut tries = 0;
+    let mut wait = Duration::from_secs(config.reconnect_wait);
+    let mut measured = 0;   // Intervals so far, for what's left of a fixed-duration test
+    let mut last_sample_opt: Option<std::time::Instant> = None;
+    loop {
+        let mut finished = false;
+        let mut gap_sent = last_sample_opt.is_none();
+        let mut error_opt = None;
+        let measured_before = measured;
+        run_iperf3(&args, &mut |out| {
+            match out {
+                Iperf3Output::Sample(sample) => {
+                    if !gap_sent {
+                        gap_sent = true;
+                        output(Iperf3Output::Gap(last_sample_opt.unwrap().elapsed()));
+                    }
+                    if is_total(&args, &sample) && sample.direction != "RX" { measured += 1; }
+                    last_sample_opt = Some(std::time::Instant::now());
+                    output(Iperf3Output::Sample(sample));
+                }
+                Iperf3Output::Summary(summary) => {
+                    finished = true;
+                    output(Iperf3Output::Summary(summary));
+                }
+                Iperf3Output::Error(err) => error_opt = Some(err),  // Only if we give up
+                other => output(other),
+            }
+        }, should_stop);
+        if finished || should_stop() { return; }
+
+        let reason = error_opt.clone().unwrap_or("iperf3 stopped".to_string());
+        // Never connected is not a disconnect
+        if last_sample_opt.is_none() || tries >= max_tries {
+            output(Iperf3Output::Error(reason));
+            return;
+        }
+        if measured > measured_before {
+            tries = 0;
+            wait = Duration::from_secs(config.reconnect_wait);
+        }
+        if args.get_time() > 0 {
+            let done = (measured as f64 * config.interval) as u32;
+            if done >= args.get_time() { return; }
+            args.time = Some(args.get_time() - done);
+            measured = 0;
+        }
+
+        tries += 1;
+        log(&format!("run_iperf3_reconnecting: {}, try {} of {}", reason, tries, max_tries));
+        output(Iperf3Output::Status(format!("{}\nReconnecting in {} s (try {} of {}) ...", reason, wait.as_secs(), tries, max_tries)));
+        let until = std::time::Instant::now() + wait;
+        while std::time::Instant::now() < until {
+            if should_stop() { return; }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        wait = (wait * 2).min(Duration::from_secs(300));
+    }
+}
+
+// Runs iperf3 with our options and passes on what it says until it exits or should_stop() is true.
+// One go at one port, None for whatever args has
+fn run_iperf3_port(args: &Args, port: Option<u16>, output: &mut dyn FnMut(Iperf3Output), should_stop: &dyn Fn() -> bool) {
+    let server_opt = args.get_server();
+    if server_opt.is_none() {
+        output(Iperf3Output::Error("Server is not selected".to_string()));
+        return;
+    }
+
+    let mut cmd = Command::new("iperf3");
+    cmd.arg("--forceflush") // Don't buffer between lines
+       .arg("--interval").arg(get_config().interval.to_string()) // Every second by default
+       .arg("--time").arg(args.get_time().to_string()) // 0 is forever
+       .arg("--format").arg("m");   // In megabits
+
+    // Typed events rather than scraping text, when this iperf3 can do it
+    let use_json = !args.text && *HAS_JSON_STREAM;
+    if use_json { cmd.arg("--json-stream"); }
+
+    // User-supplied options
+    if args.ipv6 { cmd.arg("-6"); }
+    if let Some(affinity) = &args.affinity { cmd.arg("-A").arg(affinity); }
+    if let Some(bitrate) = &args.bitrate { cmd.arg("-b").arg(bitrate); }
+    if let Some(bind) = &args.bind { cmd.arg("-B").arg(bind); }
+    if let Some(congestion) = &args.congestion { cmd.arg("-C").arg(congestion); }
+    if let Some(length) = &args.length { cmd.arg("-l").arg(length); }
+    if let Some(mss) = args.mss { cmd.arg("-M").arg(mss.to_string()); }
+    if let Some(omit) = args.omit { cmd.arg("-O").arg(omit.to_string()); }
+    if let Some(port) = port { cmd.arg("-p").arg(port.to_string()); }
+    else if args.ports.is_some() { cmd.arg("-p").arg(args.get_ports()); }
+    if args.parallel.is_some() { cmd.arg("-P").arg(args.get_parallel().to_string()); }
+    if args.reverse { cmd.arg("-R"); }
+    if let Some(tos) = args.tos { cmd.arg("-S").arg(tos.to_string()); }
+    if args.udp { cmd.arg("-u"); }
+    if let Some(window) = &args.window { cmd.arg("-w").arg(window); }
+    if args.zerocopy { cmd.arg("-Z"); }
+    if args.bidir { cmd.arg("--bidir"); }
+
+    let server_str1 = server_opt.clone().unwrap();
+    let server_str2 = server_opt.clone().unwrap();
+    if server_opt.is_some() { cmd.arg("--client").arg(server_str1); }
+    log(&format!("run_iperf3: server={}", server_str2));
+
+    cmd.stdout(Stdio::piped())
+       .stderr(Stdio::piped());
+
+    let result = cmd.spawn();
+    if result.is_err() {
+       output(Iperf3Output::Error("Could not run iperf3 - is it installed?".to_string()));
+       return;
+    }
+    let mut child = result.unwrap();
+    save_pid(child.id());
+
+    read_iperf3(args, port, use_json, child.stderr.take(), child.stdout.take(), output, should_stop);
+
+    // It has finished, failed or we're stopping it
+    let _ = child.kill();
+    let _ = child.wait();
+    forget_pid(child.id());
+}
+
+fn read_iperf3(args: &Args, port: Option<u16>, use_json: bool, stderr_result: Option<ChildStderr>, stdout_result: Option<ChildStdout>,
+               output: &mut dyn FnMut(Iperf3Output), should_stop: &dyn Fn() -> bool) {
+    //
+    // stderr
+    //
+  
+    {
+        let mut server = args.get_server_as_string();
+        if let Some(port) = port { server += &format!(" port {}", port); }
+        let stderr_msg = format!("Checking connection to {} ...", server);
+        output(Iperf3Output::Status(stderr_msg));
+
+        if stderr_result.is_none() {
+            output(Iperf3Output::Error("Could not get stderr from iperf3".to_string()));
+            return;
+        }
+        let stderr = stderr_result.unwrap();
+
+        let mut stderr_data = String::new();
+        let mut stderr_rdr = TimeoutReader::new(stderr, Duration::from_secs(get_config().timeout));
+        let _ = stderr_rdr.read_to_string(&mut stderr_data);
+        if !stderr_data.is_empty() {
+            output(Iperf3Output::Error(stderr_data.trim().to_string()));
+            return;
+        }
+        else {
+            let stderr_msg = format!("No immediate error from {} ...", server);
+            output(Iperf3Output::Status(stderr_msg));
+        }
+    }
+
+    //
+    // stdout
+    //
+
+    if stdout_result.is_none() {
+        output(Iperf3Output::Error("Could not get output from iperf3".to_string()));
+        return;
+    }
+    let stdout = stdout_result.unwrap();
+
+    let mut summary = Vec::<SummaryLine>::new();   // Text mode collects these as they come
+    let mut in_summary = false;
+    let mut byte_line = Vec::new();
     let mut stdout_data : [u8;1] = [0;1];
-    let mut stdout_rdr = TimeoutReader::new(stdout, Duration::from_secs(5));
+    let mut stdout_rdr = TimeoutReader::new(stdout, Duration::from_secs(get_config().timeout));
     loop {
-        if get_state() == State::ReloadRequested {
+        if should_stop() {
             return;
         }
 
@@ -341,8 +1391,9 @@ fn background_graph_worker(sink: &Sender<Box<dyn FnOnce(&mut Cursive) + Send>>,
         }
         let len = rdr_result.unwrap();
         if len < 1 {
-            // eprintln!("Read zero");
-            continue;
+            // iperf3 has exited
+            if !summary.is_empty() { output(Iperf3Output::Summary(summary)); }
+            return;
         }
 
         let byte = stdout_data[0];
@@ -351,57 +1402,47 @@ fn background_graph_worker(sink: &Sender<Box<dyn FnOnce(&mut Cursive) + Send>>,
             let line = String::from_utf8_lossy(&byte_line).to_string();
             byte_line.clear();
 
-            let mut remainder = "";
-            let mut bitrate: String = "".to_string();
-            let mut units: String = "".to_string();
-            let caps_main = re_main.captures(&line);
-            if caps_main.is_some() {
-                let c = caps_main.unwrap();
-                remainder = &c.get(2).unwrap().as_str().trim();
-            }
-
-            if line.contains("- - -") {
-                // End
+            let mut samples: Vec<Sample> = Vec::new();
+            if use_json {
+                match parse_json_line(&line) {
+                    Some(JsonEvent::Start(start)) => {
+                        log(&format!("run_iperf3: {} {} streams={}", start.version, start.test_start.protocol, start.test_start.num_streams));
+                        if !start.connected.is_empty() {
+                            let connected = &start.connected[0];
+                            output(Iperf3Output::Status(format!("Connected to {} port {} ...", connected.remote_host, connected.remote_port)));
+                        }
+                    }
+                    Some(JsonEvent::Interval(interval)) => {
+                        samples = interval.samples();
+                    }
+                    Some(JsonEvent::End(end)) => {
+                        let sent = end.sum_sent.clone().unwrap_or_default();
+                        let received = end.sum_received.clone().unwrap_or_default();
+                        log(&format!("run_iperf3: end sent={} received={} bits/sec", sent.bits_per_second, received.bits_per_second));
+                        if args.get_time() > 0 { output(Iperf3Output::Summary(end.summary())); }
+                    }
+                    Some(JsonEvent::Error(err)) => {
+                        output(Iperf3Output::Error(err.trim().to_string()));
+                        return;
+                    }
+                    None => {}
+                }
             }
-            else if remainder.contains("Interval") {
-                // Start or end
+            else if line.contains("- - -") {
+                in_summary = true;
             }
-            else if !remainder.is_empty() {
-                let caps_bitrate = re_bitrate.captures(&remainder);
-                if caps_bitrate.is_some() {
-                    let c = caps_bitrate.unwrap();
-                    bitrate = c.get(1).unwrap().as_str().trim().to_string();
-                    units = c.get(2).unwrap().as_str().trim().to_string();
+            else if in_summary {
+                let summary_opt = parse_text_summary_line(&line);
+                if let Some((role, sample)) = summary_opt && is_total(args, &sample) {
+                    summary.push(SummaryLine::new(&sample.direction.clone(), &role, sample));
                 }
             }
+            else {
+                samples.extend(parse_text_line(&line));
+            }
 
-            if !bitrate.is_empty() {
-                let (screen_width, screen_height) = get_screen_size();
-
-                let bitrate_result = bitrate.parse::<f64>();
-                if bitrate_result.is_err() { continue; }
-                let bitrate_f64 = bitrate_result.unwrap();
-                bitrates.push(bitrate_f64.to_owned());
-                let graph_width = screen_width - 10;
-                let graph_height = screen_height - 8;
-                while bitrates.len() > graph_width as usize {
-                    bitrates.remove(0);
-                }
-
-                // Scale
-                let bitrates_scaled = scale(&mut units, &bitrates);
-
-                //
-                // Plot
-                //
-
-                {
-                    let config = Config::default().with_width(graph_width).with_height(graph_height);
-                    let content1 = plot(bitrates_scaled.clone(), config);
-                    let units_pad = left_pad(units, 6);
-                    let content2 = replace_at_start(&content1, &units_pad);
-                    content_graph.set_content(&content2);
-                }
+            for sample in samples {
+                output(Iperf3Output::Sample(sample));
             }
         }
         else {
@@ -410,382 +1451,2914 @@ fn background_graph_worker(sink: &Sender<Box<dyn FnOnce(&mut Cursive) + Send>>,
     }
 }
 
-fn background_graph(sink: &Sender<Box<dyn FnOnce(&mut Cursive) + Send>>, content_graph: &TextContent) {
-    loop {
-        if get_state() == State::Quit { return; }
-        background_graph_worker(sink, content_graph);
-        kill_pid();
+fn background_graph_worker(sink: &CbSink, content_graph: &TextContent) {
+    save_state(State::Normal);
+    let args = get_args();
+    set_graph_title(sink, args.friendly());
 
-        loop {
-            if get_state() != State::Normal {
-                break;
-            }
-        }
+    if args.get_server().is_none() {
+        content_graph.set_content("Server is not selected.\nYou can quit, specify a server on the command line\nor select a server from the menu");
+        return;
     }
-}
 
-fn on_quit(siv: &mut Cursive) {
-    log("on_quit");
-    save_state(State::Quit);
-    kill_pid();
-    siv.quit();
+    let mut graph = Graph::default();
+    let mut remembered = false;
+    run_iperf3_reconnecting(&args, &mut |output| {
+        match output {
+            Iperf3Output::Status(status) if graph.bitrates.is_empty() => content_graph.set_content(status),
+            Iperf3Output::Status(status) => {
+                // Reconnecting - keep what we have on screen
+                let mut styled = StyledString::plain(format!("{}\n", status.replace('\n', " - ")));
+                styled.append(graph.draw(&args));
+                content_graph.set_content(styled);
+            }
+            Iperf3Output::Error(err) => content_graph.set_content(format!("{}\nYou can quit or select another server", err)),
+            Iperf3Output::Sample(sample) => {
+                record_sample(&args, &sample);
+                // The server works, so it goes in File > Recent
+                if !remembered {
+                    remembered = true;
+                    change_history(|history| history.add_recent(&args));
+                    sink.send(Box::new(add_menu)).unwrap();
+                }
+                if graph.add(&args, &sample) {
+                    content_graph.set_content(graph.draw(&args));
+                }
+            }
+            Iperf3Output::Gap(missing) => graph.add_gap(missing),
+            Iperf3Output::Summary(summary) => {
+                let text = summary_text(&args, &summary, &graph.stats);
+                sink.send(Box::new(move |s: &mut Cursive| summary_dialog(s, text))).unwrap();
+            }
+        }
+    }, &|| get_state() == State::ReloadRequested);
 }
 
 //
-// Arguments to iperf3 (and us)
+// Replay
 //
 
-#[derive(Parser, Debug, Clone, Default)]
-struct Args {   // Alphabetical order by short
-    #[arg(short = '6')]
-    ipv6: bool,
-
-    #[arg(short)]
-    ports: Option<String>,
+#[derive(Clone, Debug)]
+struct ReplayControl {
+    paused: bool,
+    speed: f64,     // 1.0 is real time
+    steps: u32,     // Intervals to show while paused
+}
 
-    #[arg(short = 'R')]
-    reverse: bool,
+impl Default for ReplayControl {
+    fn default() -> Self {
+        ReplayControl { paused: false, speed: 1.0, steps: 0 }
+    }
+}
 
-    #[arg(short)]
-    udp: bool,
+fn replay_pause() {
+    change_replay_control(|control| control.paused = !control.paused);
+}
 
-    #[arg(short = 'c')]
-    server: Option<String>,
+fn replay_step() {
+    change_replay_control(|control| { control.paused = true; control.steps += 1; });
 }
 
-impl Args {
-    #[allow(dead_code)]
-    fn clear(&mut self) {
-        self.ipv6 = false;
-        self.ports = None;
-        self.reverse = false;
-        self.udp = false;
-        self.server = None;
-    }
+fn replay_faster() {
+    change_replay_control(|control| control.speed = (control.speed * 2.0).min(64.0));
+}
 
-    fn get_ports(&self) -> String {
-        if self.ports.is_none() { return "".to_string(); }
-        return self.ports.clone().unwrap().clone();
-    }
+fn replay_slower() {
+    change_replay_control(|control| control.speed = (control.speed / 2.0).max(0.25));
+}
 
-    fn get_server(&self) -> Option<String> {
-        return self.server.clone();
-    }
+// The gap between two recorded intervals, so we can replay at the same pace
+fn record_gap(previous: &Record, current: &Record) -> Duration {
+    let previous_result = chrono::DateTime::parse_from_rfc3339(&previous.timestamp);
+    let current_result = chrono::DateTime::parse_from_rfc3339(&current.timestamp);
+    if previous_result.is_err() || current_result.is_err() { return Duration::from_secs(1); }
 
-    fn get_server_as_string(&self) -> String {
-        let opt = self.get_server();
-        if opt.is_none() { return "".to_string(); }
-        return opt.unwrap();
-    }
+    let gap = current_result.unwrap() - previous_result.unwrap();
+    let gap_std = gap.to_std().unwrap_or_default();
+    gap_std.min(Duration::from_secs(5))  // Don't make people wait through a long gap
+}
 
-    fn friendly(&self) -> String {
-        let mut out:String = String::default();
+// Waits for the gap (scaled by the speed), or while paused, for a step.
+// Returns false if the user wants to do something else.
+fn replay_wait(gap: Duration) -> bool {
+    let tick = Duration::from_millis(50);
+    let mut waited = Duration::ZERO;
+    loop {
+        if get_state() != State::Normal { return false; }
 
-        let opt = self.get_server();
-        if opt.is_some() {
-            out += &opt.unwrap();
+        let control = get_replay_control();
+        if control.paused {
+            if control.steps > 0 {
+                change_replay_control(|control| control.steps -= 1);
+                return true;
+            }
+        }
+        else if waited.as_secs_f64() * control.speed >= gap.as_secs_f64() {
+            return true;
         }
         else {
-            out += "(server not specified)";
+            waited += tick;
         }
+        std::thread::sleep(tick);
+    }
+}
 
-        if self.ipv6 { out += " IPv6"; }
-        if self.ports.is_some() { out += &(" ports ".to_owned() + &self.get_ports()) }
-        if self.reverse { out += " reverse" }
-        if self.udp { out += " udp" }
+fn replay_title(args: &Args, filename: &str, position: usize, count: usize) -> String {
+    let control = get_replay_control();
+    let basename = std::path::Path::new(filename).file_name().map_or(filename.to_string(), |name| name.to_string_lossy().to_string());
+    let mut out = format!("{} - replay of {} {}/{} at {}x", args.friendly(), basename, position, count, control.speed);
+    if control.paused { out += " (paused)"; }
+    if position == count { out += " (finished)"; }
+    out
+}
 
-        return out;
+// Feeds a recording through the same graph as a live test
+fn replay_worker(sink: &CbSink, content_graph: &TextContent, filename: &str) {
+    save_state(State::Normal);
+    log(&format!("replay_worker: {}", filename));
+
+    let records = match read_recording(filename) {
+        Ok(records) => records,
+        Err(err) => {
+            set_graph_title(sink, format!("Replay of {}", filename));
+            content_graph.set_content(format!("Could not read {}\n{}", filename, err));
+            return;
+        }
+    };
+    let args = args_from_records(&records);
+    set_graph_title(sink, replay_title(&args, filename, 0, records.len()));
+    content_graph.set_content("Replaying...");
+
+    let mut graph = Graph::default();
+    let mut previous_opt: Option<&Record> = None;
+    for (index, record) in records.iter().enumerate() {
+        let sample = record.to_sample(&args);
+        if is_total(&args, &sample) {
+            let gap = previous_opt.map_or(Duration::ZERO, |previous| record_gap(previous, record));
+            if !replay_wait(gap) { return; }
+            previous_opt = Some(record);
+        }
+
+        if graph.add(&args, &sample) {
+            content_graph.set_content(graph.draw(&args));
+            set_graph_title(sink, replay_title(&args, filename, index + 1, records.len()));
+        }
     }
+    set_graph_title(sink, replay_title(&args, filename, records.len(), records.len()));
 }
 
 //
-// Servers file
+// Benchmark
 //
 
-fn get_servers_filename() -> std::io::Result<String> {
-    let subfolder = "iperf3-tui";
-    let basename = "unparsed_servers.csv";
+// Servers to test one after the other, set by benchmark_dialog()
+#[derive(Clone, Debug)]
+struct Benchmark {
+    servers: Vec<ParsedServer>,
+    seconds: u32,   // Each
+}
 
-    let mut config_path = dirs::config_dir().expect("Could not find config directory");
-    config_path.push(subfolder);
-    std::fs::create_dir_all(&config_path)?;
-    let abs_filename = config_path.join(basename);
-    let str = abs_filename.to_str().unwrap().to_string();
-    return Ok(str);
+#[derive(Clone, Debug, Default)]
+struct BenchmarkResult {
+    server: ParsedServer,
+    mean: Option<f64>,  // Mbits/sec, None if it failed
+    peak: Option<f64>,
+    failure: String,
 }
 
-#[derive(Default,Debug)]
-#[allow(dead_code)]
-struct UnparsedServer {
-    cmd: String,
-    options: String,
-    speed: String,
-    country: String,
-    provider: String,
-    continent: String,
-    site: String,
-    status: String,
+impl BenchmarkResult {
+    fn new(server: &ParsedServer, stats: &Stats, failure: &str) -> BenchmarkResult {
+        let failure = failure.lines().next().unwrap_or("").trim().to_string();
+        let mut result = BenchmarkResult { server: server.clone(), failure, ..Default::default() };
+        if !stats.is_empty() {
+            result.mean = Some(stats.mean());
+            result.peak = Some(stats.max());
+        }
+        if result.mean.is_none() && result.failure.is_empty() { result.failure = "No data".to_string(); }
+        result
+    }
 }
 
-#[derive(Default,Debug)]
-struct ParsedServer {
-    args: Args,
-    speed: String,
-    country: String,
-    provider: String,
-    continent: String,
-    site: String,   // eg City
-    status: String,
+fn save_benchmark(benchmark_in: Option<Benchmark>) {
+    let mut benchmark = BENCHMARK.lock().unwrap();
+    *benchmark = benchmark_in;
 }
 
-impl ParsedServer {
-    fn friendly(&self) -> String {
-        let mut out = format!("{} {} {} {}", self.continent, self.country, self.site, self.provider);
-        if !self.speed.is_empty() {
-            out += &format!(" {} GB/s", self.speed);
+fn get_benchmark() -> Option<Benchmark> {
+    return BENCHMARK.lock().unwrap().clone();
+}
+
+// Runs once - picking anything else from the menu stops it
+fn benchmark_worker(sink: &CbSink, content_graph: &TextContent, benchmark: &Benchmark) {
+    save_benchmark(None);
+    save_state(State::Normal);
+    log(&format!("benchmark_worker: {} servers for {} s each", benchmark.servers.len(), benchmark.seconds));
+
+    let mut results = Vec::new();
+    let count = benchmark.servers.len();
+    for (index, server) in benchmark.servers.iter().enumerate() {
+        let mut args = server.args.with_defaults(&get_config().defaults);
+        args.time = Some(benchmark.seconds);
+        set_graph_title(sink, format!("Benchmark {} of {}: {}", index + 1, count, args.friendly()));
+
+        let mut graph = Graph::default();
+        let mut failure = String::new();
+        run_iperf3(&args, &mut |output| {
+            match output {
+                Iperf3Output::Status(status) => content_graph.set_content(status),
+                Iperf3Output::Error(err) => failure = err,
+                Iperf3Output::Sample(sample) => {
+                    if graph.add(&args, &sample) {
+                        content_graph.set_content(graph.draw(&args));
+                    }
+                }
+                Iperf3Output::Gap(_) | Iperf3Output::Summary(_) => {}
+            }
+        }, &|| get_state() == State::ReloadRequested);
+        if get_state() == State::ReloadRequested {
+            log("benchmark_worker: stopped");
+            return;
         }
-        return out;
+        results.push(BenchmarkResult::new(server, &graph.stats, &failure));
     }
+
+    content_graph.set_content("Benchmark finished.\nYou can quit or select a server");
+    sink.send(Box::new(move |s: &mut Cursive| leaderboard_dialog(s, results))).unwrap();
 }
 
-fn parse_server(unparsed: &UnparsedServer) -> ParsedServer {
-    let mut parsed = ParsedServer::default();
-    parsed.speed = unparsed.speed.clone();
-    parsed.country = unparsed.country.clone();
-    parsed.provider = unparsed.provider.clone();
-    parsed.continent = unparsed.continent.clone();
-    parsed.site = unparsed.site.clone();
-    parsed.status = unparsed.status.clone();
-    let clean_options = str::replace(&unparsed.options, ",", " ");
-    let line = unparsed.cmd.clone() + " " + &clean_options;
-    parsed.args = Args::parse_from(line.split_whitespace());
-    return parsed;
+//
+// Local server
+//
+
+// What run_iperf3_server() has to say
+enum ServerOutput {
+    Status(String),
+    Error(String),      // iperf3 -s has stopped
+    Client(String),     // A test has started, from this host
+    Stream,             // And it has another stream
+    Sample(Sample),
+    Done,               // The test has finished and we're listening again
 }
 
-fn parse_servers_file(filename: &String) -> std::io::Result<Vec<ParsedServer>> {
-    let mut out: Vec<ParsedServer> = Vec::new();
-    let file = std::fs::File::open(filename)?;
-    let reader = std::io::BufReader::new(file);
-    let mut rdr = csv::Reader::from_reader(reader);
-    for result in rdr.records() {
-        if result.is_err() { continue; }
-        let record = result.unwrap();
-        if record.len() < 8 { continue; }
-        let mut unparsed = UnparsedServer::default();
-        unparsed.cmd = record.get(0).unwrap().to_string();
-        unparsed.options = record.get(1).unwrap().to_string();
-        unparsed.speed = record.get(2).unwrap().to_string();
-        unparsed.country = record.get(3).unwrap().to_string();
-        unparsed.site = record.get(4).unwrap().to_string();
-        unparsed.provider = record.get(5).unwrap().to_string();
-        unparsed.continent = record.get(6).unwrap().to_string();
-        unparsed.status = record.get(7).unwrap().to_string();
-        let parsed = parse_server(&unparsed);
-        out.push(parsed);
-    }
-
-    if out.len() == 0 {
-        return Err(mkerr("No servers found in the file (could not parse it)"));
+// eg "Accepted connection from 192.168.1.10, port 50412"
+fn parse_accepted_line(line: &str) -> Option<String> {
+    let rest = line.strip_prefix("Accepted connection from ")?;
+    Some(rest.split(',').next().unwrap_or(rest).trim().to_string())
+}
+
+// Runs iperf3 -s until it exits or should_stop() is true. iperf3's text is the same
+// in every version, so we read that rather than JSON
+fn run_iperf3_server(port: u16, output: &mut dyn FnMut(ServerOutput), should_stop: &dyn Fn() -> bool) {
+    let mut cmd = Command::new("iperf3");
+    cmd.arg("-s")
+       .arg("-p").arg(port.to_string())
+       .arg("--forceflush")
+       .arg("--interval").arg(get_config().interval.to_string())
+       .arg("--format").arg("m")
+       .stdout(Stdio::piped())
+       .stderr(Stdio::piped());
+
+    let result = cmd.spawn();
+    if result.is_err() {
+       output(ServerOutput::Error("Could not run iperf3 - is it installed?".to_string()));
+       return;
     }
+    let mut child = result.unwrap();
+    save_pid(child.id());
+    log(&format!("run_iperf3_server: port {}", port));
 
-    Ok(out)
+    // Read stderr as it comes so a long-running server can't fill the pipe and block
+    let stderr_lines = Arc::new(Mutex::new(Vec::<String>::new()));
+    let stderr_lines_writer = stderr_lines.clone();
+    let stderr = child.stderr.take().unwrap();
+    let mut stderr_thread = Some(std::thread::spawn(move || {
+        for line in std::io::BufRead::lines(std::io::BufReader::new(stderr)).map_while(Result::ok) {
+            if !line.trim().is_empty() { stderr_lines_writer.lock().unwrap().push(line.trim().to_string()); }
+        }
+    }));
+    let mut last_error = String::new();
+
+    let mut in_summary = false;
+    let mut byte_line = Vec::new();
+    let mut stdout_data : [u8;1] = [0;1];
+    let mut stdout_rdr = TimeoutReader::new(child.stdout.take().unwrap(), Duration::from_secs(1));
+    loop {
+        if should_stop() { break; }
+        let rdr_result = stdout_rdr.read(&mut stdout_data);
+        let stopped = rdr_result.as_ref().is_ok_and(|n| *n < 1);
+        if stopped { let _ = stderr_thread.take().map(|thread| thread.join()); }   // Its last words
+        let errors: Vec<String> = std::mem::take(&mut *stderr_lines.lock().unwrap());
+        for error in errors {
+            if !stopped { output(ServerOutput::Status(error.clone())); }    // eg the client went away, iperf3 carries on
+            last_error = error;
+        }
+        if stopped {
+            // iperf3 has exited, stderr says why
+            let reason = if last_error.is_empty() { "iperf3 -s stopped".to_string() } else { last_error };
+            output(ServerOutput::Error(reason));
+            break;
+        }
+        if rdr_result.is_err() { continue; }    // No client yet
+        if stdout_data[0] != b'\n' {
+            byte_line.push(stdout_data[0]);
+            continue;
+        }
+
+        let text = String::from_utf8_lossy(&byte_line).trim_end().to_string();
+        byte_line.clear();
+        if let Some(client) = parse_accepted_line(&text) {
+            in_summary = false;
+            output(ServerOutput::Client(client));
+        }
+        else if text.starts_with("Server listening") {
+            if in_summary { output(ServerOutput::Done); }
+            in_summary = false;
+            output(ServerOutput::Status(format!("{} - waiting for a client", text.trim_matches('-').trim())));
+        }
+        else if text.contains("- - -") {
+            in_summary = true;
+        }
+        else if text.contains(" connected to ") {
+            output(ServerOutput::Stream);
+        }
+        else if !in_summary && let Some(sample) = parse_text_line(&text) {
+            output(ServerOutput::Sample(sample));
+        }
+    }
+
+    let _ = child.kill();
+    let _ = child.wait();
+    forget_pid(child.id());
 }
 
-fn servers_filename_has_content(filename: &String) -> bool {
-    let meta_result = std::fs::metadata(filename);
-    if meta_result.is_err() { return false; }
-    let len = meta_result.unwrap().len();
-    return len > 10;
+// Graphs each test as it comes in, and starts iperf3 -s again if it stops
+fn server_worker(sink: &CbSink, content_graph: &TextContent, args: &Args) {
+    save_state(State::Normal);
+    let port = first_port(&args.get_ports());
+    let title = args.friendly();
+    set_graph_title(sink, title.clone());
+    content_graph.set_content("Starting iperf3 -s ...");
+
+    let should_stop = || get_state() == State::ReloadRequested;
+    let mut graph = Graph::default();
+    let mut test_args = Args::default();    // What we can tell about the client's options
+    let mut client = String::new();
+    let mut streams = 0;
+    let mut tests = Vec::<String>::new();   // Most recent first
+    let mut wait = Duration::from_secs(get_config().reconnect_wait);
+    loop {
+        let mut status = String::new();
+        let mut error_opt = None;
+        run_iperf3_server(port, &mut |output| {
+            match output {
+                ServerOutput::Status(text) => status = text,
+                ServerOutput::Error(err) => error_opt = Some(err),
+                ServerOutput::Client(host) => {
+                    log(&format!("server_worker: test from {}", host));
+                    client = host;
+                    graph = Graph::default();
+                    test_args = Args::default();
+                    streams = 0;
+                    status = format!("Test from {}", client);
+                    set_graph_title(sink, format!("{} - test from {}", title, client));
+                }
+                ServerOutput::Stream => streams += 1,
+                ServerOutput::Sample(mut sample) => {
+                    // [TX-S] is the server sending, ie the client's download
+                    if !sample.direction.is_empty() {
+                        test_args.bidir = true;
+                        sample.direction = if sample.direction == "TX" { "RX" } else { "TX" }.to_string();
+                    }
+                    if sample.jitter_ms.is_some() { test_args.udp = true; }
+                    let per_direction = if test_args.bidir { streams / 2 } else { streams };
+                    test_args.parallel = Some(per_direction.max(1));
+                    if !graph.add(&test_args, &sample) { return; }
+                }
+                ServerOutput::Done => {
+                    let mean = if graph.stats.is_empty() { "no data".to_string() } else { friendly_bitrate(graph.stats.mean()) };
+                    tests.insert(0, format!("{} {} {}", chrono::Local::now().format("%H:%M:%S"), client, mean));
+                    tests.truncate(3);
+                }
+            }
+
+            // The graph is for the latest test, with what we're doing and the tests before it on top
+            let mut header = status.clone();
+            if !tests.is_empty() { header += &format!("\nFinished tests: {}", tests.join(", ")); }
+            let header_lines = header.lines().count() as u32;
+            let (width, rows) = Graph::default().area();
+            graph.area = Some((width, rows.saturating_sub(header_lines)));
+
+            let mut styled = StyledString::plain(header);
+            if !graph.bitrates.is_empty() {
+                styled.append_plain("\n");
+                styled.append(graph.draw(&test_args));
+            }
+            content_graph.set_content(styled);
+        }, &should_stop);
+        if should_stop() || get_state() == State::Quit { return; }
+
+        // Keep it going, eg after the port was in use
+        let reason = error_opt.unwrap_or("iperf3 -s stopped".to_string());
+        log(&format!("server_worker: {}", reason));
+        content_graph.set_content(format!("{}\nStarting iperf3 -s again in {} s ...", reason, wait.as_secs()));
+        let until = std::time::Instant::now() + wait;
+        while std::time::Instant::now() < until {
+            if should_stop() || get_state() == State::Quit { return; }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        wait = (wait * 2).min(Duration::from_secs(60));
+    }
 }
 
-fn servers_file_has_content() -> bool {
-    let filename_result = get_servers_filename();
-    if filename_result.is_err() { return false; }
-    let filename = filename_result.unwrap();
+//
+// Dashboard
+//
 
-    servers_filename_has_content(&filename)
+// One of the iperf3 clients in the dashboard, with its own panel
+#[derive(Clone)]
+struct Pane {
+    id: usize,
+    args: Args,
+    run: u32,       // Goes up each time it's started, so an older run knows to stop
+    running: bool,
+    status: TextContent,
+    graph: TextContent,
 }
 
-fn get_parsed_servers() -> std::io::Result<Vec<ParsedServer>> {
-    let filename_result = get_servers_filename();
-    if filename_result.is_err() {
-        return Err(mkerr("Could not get filename for servers"));
-    }
+fn get_pane(id: usize) -> Option<Pane> {
+    return DASHBOARD.lock().unwrap().iter().find(|pane| pane.id == id).cloned();
+}
 
-    let filename = filename_result.unwrap();
-    if !servers_filename_has_content(&filename) {
-        return Err(mkerr("Please download servers first"));
+fn change_pane(id: usize, change: impl FnOnce(&mut Pane)) {
+    let mut panes = DASHBOARD.lock().unwrap();
+    let pane_opt = panes.iter_mut().find(|pane| pane.id == id);
+    if let Some(pane) = pane_opt { change(pane); }
+}
+
+fn add_pane(args: &Args) -> usize {
+    let mut panes = DASHBOARD.lock().unwrap();
+    let id = panes.iter().map(|pane| pane.id + 1).max().unwrap_or(0);
+    panes.push(Pane { id, args: args.clone(), run: 0, running: false, status: TextContent::new("Stopped"), graph: TextContent::new("") });
+    id
+}
+
+fn remove_pane(id: usize) {
+    DASHBOARD.lock().unwrap().retain(|pane| pane.id != id);
+}
+
+fn pane_should_stop(id: usize, run: u32) -> bool {
+    if get_state() == State::Quit { return true; }
+    let pane_opt = get_pane(id);
+    pane_opt.is_none() || !pane_opt.as_ref().unwrap().running || pane_opt.unwrap().run != run
+}
+
+// Starts again if it's already running
+fn start_pane(id: usize) {
+    let mut run = 0;
+    change_pane(id, |pane| {
+        pane.run += 1;
+        pane.running = true;
+        run = pane.run;
+    });
+    std::thread::spawn(move || pane_worker(id, run));
+}
+
+fn stop_pane(id: usize) {
+    change_pane(id, |pane| {
+        if pane.running { pane.status.set_content("Stopped"); }
+        pane.running = false;
+    });
+}
+
+fn stop_all_panes() {
+    let ids: Vec<usize> = DASHBOARD.lock().unwrap().iter().map(|pane| pane.id).collect();
+    for id in ids { stop_pane(id); }
+}
+
+// Each pane gets an equal share of the screen, two across
+fn pane_size(count: usize) -> (u32, u32) {
+    let (screen_width, screen_height) = get_screen_size();
+    let columns = if count > 1 { 2 } else { 1 };
+    let rows = count.div_ceil(columns as usize).max(1) as u32;
+    let width = screen_width.saturating_sub(6) / columns;     // Dialog borders and padding
+    let height = screen_height.saturating_sub(5) / rows;      // Menu, dialog borders and buttons
+    (width, height)
+}
+
+// Less the pane's borders, status and buttons, and the graph's axis and units
+fn pane_area(count: usize) -> (u32, u32) {
+    let (width, height) = pane_size(count);
+    (width.saturating_sub(10), height.saturating_sub(6))
+}
+
+fn pane_worker(id: usize, run: u32) {
+    let pane_opt = get_pane(id);
+    if pane_opt.is_none() { return; }
+    let pane = pane_opt.unwrap();
+    log(&format!("pane_worker: {} run {}: {}", id, run, pane.args.friendly()));
+
+    let should_stop = || pane_should_stop(id, run);
+    let mut graph = Graph::default();
+    let mut error_opt = None;
+    pane.status.set_content("Starting...");
+    pane.graph.set_content("");
+    run_iperf3_reconnecting(&pane.args, &mut |output| {
+        if should_stop() { return; }
+        graph.area = Some(pane_area(DASHBOARD.lock().unwrap().len()));
+        match output {
+            Iperf3Output::Status(status) => pane.status.set_content(status.replace('\n', " - ")),
+            Iperf3Output::Error(err) => error_opt = Some(err),
+            Iperf3Output::Sample(sample) => {
+                if graph.add(&pane.args, &sample) {
+                    pane.status.set_content("Running");
+                    pane.graph.set_content(graph.draw(&pane.args));
+                }
+            }
+            Iperf3Output::Gap(missing) => graph.add_gap(missing),
+            Iperf3Output::Summary(_) => {}
+        }
+    }, &should_stop);
+    if should_stop() { return; }
+
+    let status = error_opt.map(|err| err.replace('\n', " - ")).unwrap_or("Finished".to_string());
+    pane.status.set_content(status);
+    change_pane(id, |pane| pane.running = false);
+}
+
+fn background_graph(sink: &CbSink, content_graph: &TextContent) {
+    loop {
+        if get_state() == State::Quit { return; }
+        if let Some(benchmark) = get_benchmark() {
+            benchmark_worker(sink, content_graph, &benchmark);
+        }
+        else if let Some(replay) = get_replay() {
+            replay_worker(sink, content_graph, &replay);
+        }
+        else if get_args().server_mode {
+            server_worker(sink, content_graph, &get_args());
+        }
+        else {
+            background_graph_worker(sink, content_graph);
+        }
+
+        loop {
+            if get_state() != State::Normal {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(50));  // A finished test waits here for the user
+        }
     }
+}
+
+//
+// Headless
+//
 
-    parse_servers_file(&filename)
+extern "C" fn on_interrupt(_: nix::libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
 }
 
-fn download_url(url: &str) -> Result<String, Box<dyn Error>> {
-    let client = reqwest::blocking::Client::builder().timeout(Duration::from_secs(20)).build()?;
-    let response = client.get(url).send()?;
+fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
 
-    if response.status().is_success() {
-        let body = response.text()?;
-        Ok(body)
-    } else {
-        Err(format!("Could not download: HTTP {}", response.status()).into())
+// So Ctrl-C gets a summary rather than killing us
+fn catch_interrupts() {
+    let action = SigAction::new(SigHandler::Handler(on_interrupt), SaFlags::empty(), SigSet::empty());
+    unsafe {
+        let _ = sigaction(Signal::SIGINT, &action);
+        let _ = sigaction(Signal::SIGTERM, &action);
     }
 }
 
-// Doesn't return an error - but sets in the status
-fn download_servers(sink: &Sender<Box<dyn FnOnce(&mut Cursive) + Send>>) {
-    let result = download_url("https://export.iperf3serverlist.net/unparsed_iperf3_servers.csv");
+// eg 941.23 -> "941.23 Mbits/sec", 9380.0 -> "9.38 Gbits/sec"
+fn friendly_bitrate(mbits_per_second: f64) -> String {
+    let mut units = "Mbits".to_string();
+    let scaled = scale(&mut units, &[mbits_per_second]);
+    format!("{:.2} {}/sec", scaled[0], units)
+}
 
-    let status;
-    if result.is_err() {
-        status = result.unwrap_err().to_string();
+// One line per interval, eg
+// 13:10:01 upload    941.23 Mbits/sec  retr 0  cwnd 376 KB
+fn headless_line(args: &Args, sample: &Sample) -> String {
+    let upload = if args.bidir { sample.direction != "RX" } else { !args.reverse };
+    let mut out = format!("{} {:8} {:>17}",
+        chrono::Local::now().format("%H:%M:%S"),
+        if upload { "upload" } else { "download" },
+        friendly_bitrate(sample.mbits_per_second()));
+    if !args.udp && let Some(retransmits) = sample.retransmits { out += &format!("  retr {}", retransmits); }
+    if let Some(snd_cwnd) = sample.snd_cwnd { out += &format!("  cwnd {}", friendly_bytes(snd_cwnd)); }
+    if let Some(jitter_ms) = sample.jitter_ms { out += &format!("  jitter {:.3} ms", jitter_ms); }
+    if let Some(lost_percent) = sample.lost_percent { out += &format!("  loss {:.2} %", lost_percent); }
+    out
+}
+
+fn headless_summary(name: &str, stats: &Stats) -> String {
+    if stats.is_empty() { return format!("{}: no intervals", name); }
+    format!("{}: {} intervals, average {}, median {}, p95 {}, min {}, max {}, stddev {}",
+        name, stats.values.len(), friendly_bitrate(stats.mean()), friendly_bitrate(stats.median()), friendly_bitrate(stats.percentile(95.0)),
+        friendly_bitrate(stats.min()), friendly_bitrate(stats.max()), friendly_bitrate(stats.stddev()))
+}
+
+// Runs the test without cursive, printing to stdout. Returns the exit code.
+fn run_headless(args: &Args) -> i32 {
+    if args.get_server().is_none() {
+        eprintln!("Please specify a server with -c in headless mode");
+        return 2;
     }
-    else {
-        let body = result.unwrap();
-        let filename_result = get_servers_filename();
-        if filename_result.is_err() {
-            status = filename_result.unwrap_err().to_string();
-        }
-        else {
-            let filename = filename_result.unwrap();
-            let save_result = save_file_contents(&filename, &body);
-            if save_result.is_err() {
-                status = "Downloaded list of servers but could not save to a file - permission?".to_string();
+    catch_interrupts();
+    eprintln!("Testing {} - Ctrl-C to stop", args.friendly());
+
+    let mut uploads = Stats::default();
+    let mut downloads = Stats::default();
+    let mut recent = Vec::<f64>::new();  // For the sparkline
+    let mut failed = false;
+    let mut final_summary = Vec::<SummaryLine>::new();
+    run_iperf3_reconnecting(args, &mut |output| {
+        match output {
+            Iperf3Output::Status(status) => eprintln!("{}", status),
+            Iperf3Output::Error(err) => {
+                eprintln!("{}", err);
+                failed = true;
             }
-            else {
-                let servers_result = get_parsed_servers();
-                if servers_result.is_err() {
-                    status = format!("Downloaded list of servers but {}", servers_result.unwrap_err());
+            Iperf3Output::Summary(summary) => final_summary = summary,
+            Iperf3Output::Gap(missing) => {
+                if args.sparkline { println!(); }
+                println!("--- reconnected, {} s not measured ---", missing.as_secs());
+            }
+            Iperf3Output::Sample(sample) => {
+                record_sample(args, &sample);
+                if !is_total(args, &sample) { return; }
+                let upload = if args.bidir { sample.direction != "RX" } else { !args.reverse };
+                if upload { uploads.add(sample.mbits_per_second()); } else { downloads.add(sample.mbits_per_second()); }
+
+                if args.sparkline {
+                    recent.push(sample.mbits_per_second());
+                    while recent.len() > 60 { recent.remove(0); }
+                    print!("\r{} {:>17} ", sparkline(&recent), friendly_bitrate(sample.mbits_per_second()));
+                    let _ = std::io::stdout().flush();
                 }
                 else {
-                    let servers = servers_result.unwrap();
-                    status = format!("Downloaded {} servers", servers.len());
+                    println!("{}", headless_line(args, &sample));
                 }
             }
         }
+    }, &is_interrupted);
+
+    if args.sparkline { println!(); }
+    for line in &final_summary {
+        println!("{}", line.friendly());
     }
-    sink.send(Box::new(|s: &mut Cursive| {
-        s.call_on_name("status", |view: &mut NamedView<TextView> | view.get_mut().set_content(status));
-    })).unwrap();
+    if !uploads.is_empty() { println!("{}", headless_summary("Upload", &uploads)); }
+    if !downloads.is_empty() { println!("{}", headless_summary("Download", &downloads)); }
+    if failed || (uploads.is_empty() && downloads.is_empty()) { return 1; }
+    0
+}
 
+fn on_quit(siv: &mut Cursive) {
+    log("on_quit");
+    save_state(State::Quit);
+    kill_pids();
+    siv.quit();
 }
 
 //
-//  Dialogs
+// User configuration
 //
 
-fn download_servers_dialog(siv: &mut Cursive) {
-    let sink = siv.cb_sink().clone();
-    siv.add_layer(
-        Dialog::new()
-        .title("Download list of iperf3 servers")
-        .padding_lrtb(1, 1, 1, 0)
-        .content(
-            TextView::new("Downloading...")
-                .with_name("status")
-                .fixed_width(50),
-        )
-        .button("Close", |s| { s.pop_layer(); })
-    );
+// ~/.config/iperf3-tui/config.toml - every setting is optional, eg
+//   interval = 0.5
+//   [defaults]
+//   parallel = 4
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+struct UserConfig {
+    interval: f64,          // Seconds between samples
+    timeout: u64,           // Seconds to wait for iperf3 to say something
+    servers_url: String,
+    log_file: String,
+    graph_margin_width: u32,    // Columns around the graph for the axis and borders
+    graph_margin_height: u32,   // Rows for the menu, borders and title
+    busy_retries: u32,      // Times to go round the port range again when every port is busy
+    busy_wait: u64,         // Seconds before the first retry, doubling each time
+    reconnect_wait: u64,    // Seconds before restarting iperf3 with --reconnect, doubling each time
+    refresh_days: u32,      // Get a new server list at launch when ours is this old, 0 is never
+    sources: Vec<ServerSource>, // More server lists, as well as servers_url
+    defaults: Args,         // For anything not on the command line
+}
 
-    std::thread::spawn(move || { download_servers(&sink) });
+impl Default for UserConfig {
+    fn default() -> Self {
+        UserConfig {
+            interval: 1.0,
+            timeout: 5,
+            servers_url: "https://export.iperf3serverlist.net/unparsed_iperf3_servers.csv".to_string(),
+            log_file: "/tmp/iperf3-tui.log".to_string(),   // Not /var/log because not all users are allowed
+            graph_margin_width: 10,
+            graph_margin_height: 8,
+            busy_retries: 0,
+            busy_wait: 5,
+            reconnect_wait: 2,
+            refresh_days: 0,
+            sources: Vec::new(),
+            defaults: Args::default(),
+        }
+    }
 }
 
-fn select_server_dialog(siv: &mut Cursive) {
-    let servers_result = get_parsed_servers();
-    if servers_result.is_err() {
-        siv.add_layer(
-            Dialog::new()
-            .title("Select an iperf3 server")
-            .padding_lrtb(1, 1, 1, 0)
-            .content(TextView::new(servers_result.unwrap_err().to_string()))
-            .button("Close", |s| { s.pop_layer(); }));
-        return;
+fn parse_config(text: &str) -> Result<UserConfig, String> {
+    let config: UserConfig = toml::from_str(text).map_err(|err: toml::de::Error| err.to_string().trim_end().to_string())?;
+    if config.interval.is_nan() || config.interval < 0.1 { return Err("interval must be at least 0.1 seconds".to_string()); }
+    if config.timeout == 0 { return Err("timeout must be at least 1 second".to_string()); }
+    if config.defaults.reverse && config.defaults.bidir { return Err("defaults can't have both reverse and bidir".to_string()); }
+    if config.defaults.parallel.is_some() && !(1..=128).contains(&config.defaults.parallel.unwrap()) { return Err("defaults.parallel must be from 1 to 128".to_string()); }
+    validate_args(&config.defaults).map_err(|err| format!("defaults: {}", err))?;
+    let mut names = vec![PUBLIC_SOURCE.to_string()];
+    for source in &config.sources {
+        if source.name.trim().is_empty() { return Err("every [[sources]] needs a name".to_string()); }
+        if names.contains(&source.name) { return Err(format!("there is more than one source called {}", source.name)); }
+        if source.url.is_some() == source.path.is_some() { return Err(format!("source {} needs either a url or a path", source.name)); }
+        names.push(source.name.clone());
     }
-    let servers = servers_result.unwrap();
+    Ok(config)
+}
 
-    let mut select = SelectView::<ParsedServer>::new()
-        .on_submit(|s, item| {
-            save_args(&item.args);
-            save_state(State::ReloadRequested);
-            log(&format!("select_server_dialog: user selected {} with {}", item.friendly(), item.args.friendly()));
+// A missing file is fine, a broken one isn't
+fn load_config(filename_opt: Option<String>) -> Result<UserConfig, String> {
+    let must_exist = filename_opt.is_some();
+    let filename = match filename_opt {
+        Some(filename) => filename,
+        None => {
+            let filename_result = get_config_filename("config.toml");
+            if filename_result.is_err() { return Ok(UserConfig::default()); }
+            filename_result.unwrap()
+        }
+    };
+
+    let contents = match std::fs::read_to_string(&filename) {
+        Ok(contents) => contents,
+        Err(err) if !must_exist && err.kind() == std::io::ErrorKind::NotFound => return Ok(UserConfig::default()),
+        Err(err) => return Err(format!("{}: {}", filename, err)),
+    };
+    parse_config(&contents).map_err(|err| format!("{}: {}", filename, err))
+}
+
+//
+// Arguments to iperf3 (and us)
+//
+
+// Saved in history.json, so only what describes the test itself is serialized.
+// Everything can be read from the [defaults] of the config file
+#[derive(Parser, Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+struct Args {   // Alphabetical order by short
+    #[arg(short = '6')]
+    ipv6: bool,
+
+    /// CPU affinity, eg 2 or 2,3 for the client's and the server's
+    #[arg(short = 'A', value_name = "N[,M]", value_parser = parse_affinity_arg)]
+    affinity: Option<String>,
+
+    /// Target bitrate in bits/sec, eg 100M, or 100M/10 for bursts of 10 packets
+    #[arg(short = 'b', value_name = "RATE", value_parser = parse_bitrate_arg)]
+    bitrate: Option<String>,
+
+    /// Bind to this local address
+    #[arg(short = 'B', value_name = "HOST", value_parser = parse_bind_arg)]
+    bind: Option<String>,
+
+    /// TCP congestion control algorithm, eg bbr or cubic
+    #[arg(short = 'C', value_name = "ALGO", value_parser = parse_congestion_arg)]
+    congestion: Option<String>,
+
+    /// Read/write buffer length, eg 128K
+    #[arg(short = 'l', value_name = "SIZE", value_parser = parse_size_arg)]
+    length: Option<String>,
+
+    /// TCP maximum segment size
+    #[arg(short = 'M', value_name = "BYTES", value_parser = parse_mss_arg)]
+    mss: Option<u32>,
+
+    /// Seconds at the start to leave out, eg while TCP ramps up
+    #[arg(short = 'O', value_name = "SECONDS", value_parser = parse_omit_arg)]
+    omit: Option<u32>,
+
+    #[arg(short)]
+    ports: Option<String>,
+
+    /// Number of parallel streams
+    #[arg(short = 'P', long, value_parser = clap::value_parser!(u32).range(1..=128))]
+    parallel: Option<u32>,
+
+    #[arg(short = 'R')]
+    reverse: bool,
+
+    /// Type of service, eg 0x10 or 16
+    #[arg(short = 'S', value_name = "TOS", value_parser = parse_tos_arg)]
+    tos: Option<u8>,
+
+    /// Seconds to test for, 0 is forever
+    #[arg(short = 't', long)]
+    time: Option<u32>,
+
+    #[arg(short)]
+    udp: bool,
+
+    /// TCP window size, eg 256K
+    #[arg(short = 'w', value_name = "SIZE", value_parser = parse_size_arg)]
+    window: Option<String>,
+
+    /// Send with zero copy
+    #[arg(short = 'Z')]
+    zerocopy: bool,
+
+    /// Send and receive at the same time (iperf3 3.7 and later)
+    #[arg(long, conflicts_with = "reverse")]
+    bidir: bool,
+
+    /// Restart iperf3 up to this many times in a row if it stops part way through
+    #[arg(long, value_name = "TRIES")]
+    reconnect: Option<u32>,
+
+    #[arg(short = 'c')]
+    server: Option<String>,
+
+    // Long-only options for us rather than iperf3
+
+    /// Scrape iperf3's text output even if it supports --json-stream
+    #[arg(long)]
+    #[serde(skip_serializing)]
+    text: bool,
+
+    /// Append every interval to this file - CSV if it ends in .csv, otherwise JSON lines
+    #[arg(long, value_name = "PATH")]
+    #[serde(skip_serializing)]
+    record: Option<String>,
+
+    /// Show a recording made with --record instead of running iperf3
+    #[arg(long, value_name = "PATH")]
+    #[serde(skip_serializing)]
+    replay: Option<String>,
+
+    /// Print a line per interval instead of drawing the graph, eg for scripts
+    #[arg(long)]
+    #[serde(skip_serializing)]
+    headless: bool,
+
+    /// With --headless, redraw a sparkline on one line instead
+    #[arg(long, requires = "headless")]
+    #[serde(skip_serializing)]
+    sparkline: bool,
+
+    /// Run iperf3 -s on the -p port (default 5201) and graph the tests clients run against it
+    #[arg(long, conflicts_with_all = ["server", "headless", "replay"])]
+    #[serde(skip_serializing)]
+    server_mode: bool,
+
+    /// Read defaults from this file instead of ~/.config/iperf3-tui/config.toml
+    #[arg(long, value_name = "PATH")]
+    #[serde(skip)]
+    config: Option<String>,
+
+    // Turn off a flag the config file's [defaults] turns on
+
+    /// Don't use IPv6 even if the config file says to
+    #[arg(long, conflicts_with = "ipv6")]
+    #[serde(skip)]
+    no_ipv6: bool,
+
+    /// Don't reverse even if the config file says to
+    #[arg(long, conflicts_with = "reverse")]
+    #[serde(skip)]
+    no_reverse: bool,
+
+    /// Don't use UDP even if the config file says to
+    #[arg(long, conflicts_with = "udp")]
+    #[serde(skip)]
+    no_udp: bool,
+
+    /// Don't use zero copy even if the config file says to
+    #[arg(long, conflicts_with = "zerocopy")]
+    #[serde(skip)]
+    no_zerocopy: bool,
+
+    /// Don't test both ways even if the config file says to
+    #[arg(long, conflicts_with = "bidir")]
+    #[serde(skip)]
+    no_bidir: bool,
+
+    /// Don't scrape text even if the config file says to
+    #[arg(long, conflicts_with = "text")]
+    #[serde(skip)]
+    no_text: bool,
+
+    /// Draw the graph even if the config file says --headless
+    #[arg(long, conflicts_with = "headless")]
+    #[serde(skip)]
+    no_headless: bool,
+
+    /// Don't draw a sparkline even if the config file says to
+    #[arg(long, conflicts_with = "sparkline")]
+    #[serde(skip)]
+    no_sparkline: bool,
+}
+
+impl Args {
+    #[allow(dead_code)]
+    fn clear(&mut self) {
+        self.ipv6 = false;
+        self.affinity = None;
+        self.bitrate = None;
+        self.bind = None;
+        self.congestion = None;
+        self.length = None;
+        self.mss = None;
+        self.omit = None;
+        self.ports = None;
+        self.parallel = None;
+        self.reverse = false;
+        self.tos = None;
+        self.time = None;
+        self.udp = false;
+        self.window = None;
+        self.zerocopy = false;
+        self.bidir = false;
+        self.server = None;
+        self.text = false;
+        self.record = None;
+        self.replay = None;
+        self.headless = false;
+        self.sparkline = false;
+    }
+
+    // Fills in what wasn't given on the command line from the config file.
+    // A flag the config turns on can be turned off again with its --no-* option
+    fn with_defaults(&self, defaults: &Args) -> Args {
+        let mut out = self.clone();
+        out.ipv6 |= defaults.ipv6 && !self.no_ipv6;
+        out.affinity = self.affinity.clone().or(defaults.affinity.clone());
+        out.bitrate = self.bitrate.clone().or(defaults.bitrate.clone());
+        out.bind = self.bind.clone().or(defaults.bind.clone());
+        out.congestion = self.congestion.clone().or(defaults.congestion.clone());
+        out.length = self.length.clone().or(defaults.length.clone());
+        out.mss = self.mss.or(defaults.mss);
+        out.omit = self.omit.or(defaults.omit);
+        out.ports = self.ports.clone().or(defaults.ports.clone());
+        out.parallel = self.parallel.or(defaults.parallel);
+        out.tos = self.tos.or(defaults.tos);
+        out.time = self.time.or(defaults.time);
+        out.udp |= defaults.udp && !self.no_udp;
+        out.window = self.window.clone().or(defaults.window.clone());
+        out.zerocopy |= defaults.zerocopy && !self.no_zerocopy;
+        if !self.reverse && !self.bidir {   // They don't go together so the command line wins
+            out.reverse = defaults.reverse && !self.no_reverse;
+            out.bidir = defaults.bidir && !self.no_bidir;
+        }
+        out.reconnect = self.reconnect.or(defaults.reconnect);
+        out.server = self.server.clone().or(defaults.server.clone());
+        out.text |= defaults.text && !self.no_text;
+        out.record = self.record.clone().or(defaults.record.clone());
+        out.replay = self.replay.clone().or(defaults.replay.clone());
+        out.headless |= defaults.headless && !self.no_headless;
+        out.sparkline |= defaults.sparkline && !self.no_sparkline;
+        out
+    }
+
+    #[allow(clippy::needless_return)]
+    fn get_ports(&self) -> String {
+        if self.ports.is_none() { return "".to_string(); }
+        return self.ports.clone().unwrap().clone();
+    }
+
+    fn get_parallel(&self) -> u32 {
+        self.parallel.unwrap_or(1)
+    }
+
+    fn get_time(&self) -> u32 {
+        self.time.unwrap_or(0)
+    }
+
+    #[allow(clippy::needless_return)]
+    fn get_server(&self) -> Option<String> {
+        return self.server.clone();
+    }
+
+    #[allow(clippy::needless_return)]
+    fn get_server_as_string(&self) -> String {
+        let opt = self.get_server();
+        if opt.is_none() { return "".to_string(); }
+        return opt.unwrap();
+    }
+
+    #[allow(clippy::needless_return, clippy::unnecessary_unwrap)]
+    fn friendly(&self) -> String {
+        if self.server_mode { return format!("Local server on port {}", first_port(&self.get_ports())); }
+        let mut out:String = String::default();
+
+        let opt = self.get_server();
+        if opt.is_some() {
+            out += &opt.unwrap();
+        }
+        else {
+            out += "(server not specified)";
+        }
+
+        if self.ipv6 { out += " IPv6"; }
+        if self.affinity.is_some() { out += &format!(" CPU {}", self.affinity.clone().unwrap()) }
+        if self.bitrate.is_some() { out += &format!(" bitrate {}", self.bitrate.clone().unwrap()) }
+        if self.bind.is_some() { out += &format!(" from {}", self.bind.clone().unwrap()) }
+        if self.congestion.is_some() { out += &format!(" congestion {}", self.congestion.clone().unwrap()) }
+        if self.length.is_some() { out += &format!(" length {}", self.length.clone().unwrap()) }
+        if self.mss.is_some() { out += &format!(" MSS {}", self.mss.unwrap()) }
+        if self.omit.is_some() { out += &format!(" omit {} s", self.omit.unwrap()) }
+        if self.ports.is_some() { out += &(" ports ".to_owned() + &self.get_ports()) }
+        if self.get_parallel() > 1 { out += &format!(" {} streams", self.get_parallel()) }
+        if self.reverse { out += " reverse" }
+        if self.tos.is_some() { out += &format!(" TOS {:#04x}", self.tos.unwrap()) }
+        if self.get_time() > 0 { out += &format!(" {} seconds", self.get_time()) }
+        if self.udp { out += " udp" }
+        if self.window.is_some() { out += &format!(" window {}", self.window.clone().unwrap()) }
+        if self.zerocopy { out += " zero-copy" }
+        if self.bidir { out += " bidirectional" }
+
+        return out;
+    }
+
+    // Whether they'd run the same test. Our own options like --record aren't saved in
+    // history.json, so they don't count
+    fn same_test(&self, other: &Args) -> bool {
+        serde_json::to_value(self).ok() == serde_json::to_value(other).ok()
+    }
+
+    // eg "-c iperf.example.com -p 5201 -R", the options that say what to test
+    fn command_line(&self) -> String {
+        let mut out = Vec::<String>::new();
+        if self.ipv6 { out.push("-6".to_string()); }
+        if let Some(affinity) = &self.affinity { out.push(format!("-A {}", affinity)); }
+        if let Some(bitrate) = &self.bitrate { out.push(format!("-b {}", bitrate)); }
+        if let Some(bind) = &self.bind { out.push(format!("-B {}", bind)); }
+        if let Some(congestion) = &self.congestion { out.push(format!("-C {}", congestion)); }
+        if let Some(length) = &self.length { out.push(format!("-l {}", length)); }
+        if let Some(mss) = self.mss { out.push(format!("-M {}", mss)); }
+        if let Some(omit) = self.omit { out.push(format!("-O {}", omit)); }
+        if self.ports.is_some() { out.push(format!("-p {}", self.get_ports())); }
+        if self.parallel.is_some() { out.push(format!("-P {}", self.get_parallel())); }
+        if self.reverse { out.push("-R".to_string()); }
+        if let Some(tos) = self.tos { out.push(format!("-S {}", tos)); }
+        if self.time.is_some() { out.push(format!("-t {}", self.get_time())); }
+        if self.udp { out.push("-u".to_string()); }
+        if let Some(window) = &self.window { out.push(format!("-w {}", window)); }
+        if self.zerocopy { out.push("-Z".to_string()); }
+        if self.bidir { out.push("--bidir".to_string()); }
+        if let Some(reconnect) = self.reconnect { out.push(format!("--reconnect {}", reconnect)); }
+        if self.server.is_some() { out.push(format!("-c {}", self.get_server_as_string())); }
+        out.join(" ")
+    }
+}
+
+// The options from the config file haven't been through clap, so check them the same way
+fn validate_args(args: &Args) -> Result<(), String> {
+    if let Some(affinity) = &args.affinity { parse_affinity_arg(affinity)?; }
+    if let Some(bitrate) = &args.bitrate { parse_bitrate_arg(bitrate)?; }
+    if let Some(bind) = &args.bind { parse_bind_arg(bind)?; }
+    if let Some(congestion) = &args.congestion { parse_congestion_arg(congestion)?; }
+    if let Some(length) = &args.length { parse_size_arg(length)?; }
+    if let Some(mss) = args.mss { parse_mss_arg(&mss.to_string())?; }
+    if let Some(omit) = args.omit { parse_omit_arg(&omit.to_string())?; }
+    if let Some(window) = &args.window { parse_size_arg(window)?; }
+    Ok(())
+}
+
+// eg 2 or 2,3
+fn parse_affinity_arg(s: &str) -> Result<String, String> {
+    if !RE_AFFINITY_ARG.is_match(s.trim()) { return Err(format!("CPU affinity should be like 2 or 2,3, not '{}'", s)); }
+    Ok(s.trim().to_string())
+}
+
+// eg 100M or 100M/10, in bits/sec
+fn parse_bitrate_arg(s: &str) -> Result<String, String> {
+    if !RE_BITRATE_ARG.is_match(s.trim()) { return Err(format!("Bitrate should be like 100M or 100M/10, not '{}'", s)); }
+    Ok(s.trim().to_string())
+}
+
+// An address or host name, with iperf3's %interface if wanted
+fn parse_bind_arg(s: &str) -> Result<String, String> {
+    if s.trim().is_empty() || s.trim().contains(char::is_whitespace) { return Err(format!("Bind address should be an address or host name, not '{}'", s)); }
+    Ok(s.trim().to_string())
+}
+
+// The kernel's names are up to 15 characters
+fn parse_congestion_arg(s: &str) -> Result<String, String> {
+    if !RE_CONGESTION_ARG.is_match(s.trim()) { return Err(format!("Congestion algorithm should be a name like bbr or cubic, not '{}'", s)); }
+    Ok(s.trim().to_string())
+}
+
+// eg 128K, for -l and -w
+fn parse_size_arg(s: &str) -> Result<String, String> {
+    if !RE_SIZE_ARG.is_match(s.trim()) { return Err(format!("Size should be like 128K or 2M, not '{}'", s)); }
+    Ok(s.trim().to_string())
+}
+
+// iperf3 won't go over 9 KB
+fn parse_mss_arg(s: &str) -> Result<u32, String> {
+    let mss_result = s.trim().parse::<u32>();
+    if mss_result.is_err() || !(1..=9216).contains(mss_result.as_ref().unwrap()) { return Err(format!("MSS should be a number of bytes from 1 to 9216, not '{}'", s)); }
+    Ok(mss_result.unwrap())
+}
+
+// iperf3 won't omit more than 10 minutes
+fn parse_omit_arg(s: &str) -> Result<u32, String> {
+    let omit_result = s.trim().parse::<u32>();
+    if omit_result.is_err() || omit_result.clone().unwrap() > 600 { return Err(format!("Omit should be a number of seconds from 0 to 600, not '{}'", s)); }
+    Ok(omit_result.unwrap())
+}
+
+// Decimal, or hex with 0x like iperf3 takes
+// Like iperf3's strtol(), a leading 0x is hex and a leading 0 is octal, eg 020 is 16
+fn parse_tos_arg(s: &str) -> Result<u8, String> {
+    let trimmed = s.trim();
+    let hex_opt = trimmed.strip_prefix("0x").or(trimmed.strip_prefix("0X"));
+    let octal_opt = if trimmed.len() > 1 { trimmed.strip_prefix('0') } else { None };
+    let tos_result = if let Some(hex) = hex_opt { u8::from_str_radix(hex, 16) }
+        else if let Some(octal) = octal_opt { u8::from_str_radix(octal, 8) }
+        else { trimmed.parse::<u8>() };
+    if tos_result.is_err() { return Err(format!("TOS should be a number from 0 to 255, 0x00 to 0xff or 000 to 0377, not '{}'", s)); }
+    Ok(tos_result.unwrap())
+}
+
+//
+// Servers file
+//
+
+#[allow(clippy::needless_return)]
+fn get_servers_filename() -> std::io::Result<String> {
+    return get_config_filename("unparsed_servers.csv");
+}
+
+// eg ~/.config/iperf3-tui/history.json
+fn get_config_filename(basename: &str) -> std::io::Result<String> {
+    let subfolder = "iperf3-tui";
+
+    let mut config_path = dirs::config_dir().expect("Could not find config directory");
+    config_path.push(subfolder);
+    std::fs::create_dir_all(&config_path)?;
+    let abs_filename = config_path.join(basename);
+    let str = abs_filename.to_str().unwrap().to_string();
+    Ok(str)
+}
+
+#[derive(Default,Debug)]
+#[allow(dead_code)]
+struct UnparsedServer {
+    cmd: String,
+    options: String,
+    speed: String,
+    country: String,
+    provider: String,
+    continent: String,
+    site: String,
+    status: String,
+}
+
+#[derive(Default,Debug,Clone)]
+struct ParsedServer {
+    args: Args,
+    marker: String, // "★" for a favourite, "↺" for a recent test, otherwise empty
+    speed: String,
+    country: String,
+    provider: String,
+    continent: String,
+    site: String,   // eg City
+    status: String,
+    ipv6_capable: bool,
+    source: String, // The name of the list it came from
+    reachability: Reachability, // From history.json, filled in for Select Server
+}
+
+impl ParsedServer {
+    #[allow(clippy::needless_return)]
+    fn friendly(&self) -> String {
+        let mut out = format!("{} {} {} {}", self.continent, self.country, self.site, self.provider);
+        if !self.speed.is_empty() {
+            out += &format!(" {} GB/s", self.speed);
+        }
+        return out;
+    }
+
+    // The iperf3 control port - the first of a range
+    fn control_port(&self) -> u16 {
+        first_port(&self.args.get_ports())
+    }
+
+    // eg "iperf.example.com:5201", for LATENCIES
+    fn latency_key(&self) -> String {
+        format!("{}:{}", self.args.get_server_as_string(), self.control_port())
+    }
+}
+
+// eg "5200-5209" -> 5200, with iperf3's default if there isn't one
+fn first_port(ports: &str) -> u16 {
+    let first = ports.split('-').next().unwrap_or("").trim();
+    first.parse::<u16>().unwrap_or(5201)
+}
+
+// iperf3's other options that take a value, so parse_server_command() can skip it
+const IPERF3_VALUE_FLAGS: [&str; 50] = ["-f", "--format", "-i", "--interval", "-I", "--pidfile", "-F", "--file", "-A", "--affinity",
+    "-B", "--bind", "--bind-dev", "-b", "--bitrate", "--bandwidth", "--pacing-timer", "--fq-rate", "-t", "--time", "-n", "--bytes",
+    "-k", "--blockcount", "-l", "--length", "--cport", "-P", "--parallel", "-w", "--window", "-C", "--congestion",
+    "-M", "--set-mss", "-S", "--tos", "--dscp", "-L", "--flowlabel", "-X", "--xbind", "-O", "--omit",
+    "-T", "--title", "--connect-timeout", "--extra-data", "--logfile", "--username"];
+
+// The list's cmd and options columns are written by hand, eg "iperf3 -c iperf.example.com"
+// and "-p 5200-5209,IPv4/IPv6". We only need the host, the ports and whether it does IPv6,
+// so anything else is skipped rather than given to clap, which would exit on a flag it doesn't know
+fn parse_server_command(cmd: &str, options: &str) -> Result<Args, String> {
+    let line = format!("{} {}", cmd, options).replace(',', " ");
+    let mut tokens = line.split_whitespace().peekable();
+    let mut args = Args::default();
+    let mut bare = Vec::<String>::new();   // Words that aren't flags, in case there's no -c
+    while let Some(token) = tokens.next() {
+        let (flag, attached) = if token.starts_with("--") && token.contains('=') {
+            let (flag, value) = token.split_once('=').unwrap();
+            (flag, Some(value.to_string()))
+        }
+        else if token.len() > 2 && (token.starts_with("-c") || token.starts_with("-p")) {
+            (&token[..2], Some(token[2..].to_string()))
+        }
+        else {
+            (token, None)
+        };
+
+        match flag {
+            "-c" | "--client" => {
+                let host_opt = attached.or(tokens.next().map(|host| host.to_string()));
+                if host_opt.is_none() { return Err("-c without a host".to_string()); }
+                args.server = host_opt;
+            }
+            "-p" | "--port" => {
+                let ports_opt = attached.or(tokens.next().map(|ports| ports.to_string()));
+                if ports_opt.is_none() { return Err("-p without a port".to_string()); }
+                let ports = ports_opt.unwrap();
+                if port_range(&ports).is_none() { return Err(format!("'{}' isn't a port or a range of up to 100 ports", ports)); }
+                args.ports = Some(ports);
+            }
+            "-6" | "--version6" => args.ipv6 = true,
+            _ if attached.is_none() && IPERF3_VALUE_FLAGS.contains(&flag) => { tokens.next(); }   // So eg -B 1.2.3.4 isn't the host
+            _ if flag.starts_with('-') => {}    // Not one we need
+            _ => bare.push(token.to_string()),
+        }
+    }
+
+    // Some rows are just "iperf3 host"
+    if args.server.is_none() {
+        args.server = bare.into_iter().find(|word| !word.starts_with("iperf") && (word.contains('.') || word.contains(':')));
+    }
+    if args.server.is_none() { return Err("no server address".to_string()); }
+    Ok(args)
+}
+
+fn parse_server(unparsed: &UnparsedServer) -> Result<ParsedServer, String> {
+    let args = parse_server_command(&unparsed.cmd, &unparsed.options)?;
+    Ok(ParsedServer {
+        ipv6_capable: args.ipv6 || unparsed.options.to_lowercase().contains("ipv6"),
+        args,
+        speed: unparsed.speed.clone(),
+        country: unparsed.country.clone(),
+        provider: unparsed.provider.clone(),
+        continent: unparsed.continent.clone(),
+        site: unparsed.site.clone(),
+        status: unparsed.status.clone(),
+        ..Default::default()
+    })
+}
+
+// Rows we can't use are skipped, with why in warnings
+fn parse_servers_file(filename: &str, warnings: &mut Vec<String>) -> std::io::Result<Vec<ParsedServer>> {
+    let mut out: Vec<ParsedServer> = Vec::new();
+    let file = std::fs::File::open(filename)?;
+    let reader = std::io::BufReader::new(file);
+    let mut rdr = csv::Reader::from_reader(reader);
+    for result in rdr.records() {
+        let record = match result {
+            Ok(record) => record,
+            Err(err) => {
+                warnings.push(err.to_string());
+                continue;
+            }
+        };
+        let line = record.position().map_or(0, |position| position.line());
+        if record.len() < 8 {
+            warnings.push(format!("Line {}: {} columns, expected 8", line, record.len()));
+            continue;
+        }
+        let unparsed = UnparsedServer {
+            cmd: record.get(0).unwrap().to_string(),
+            options: record.get(1).unwrap().to_string(),
+            speed: record.get(2).unwrap().to_string(),
+            country: record.get(3).unwrap().to_string(),
+            site: record.get(4).unwrap().to_string(),
+            provider: record.get(5).unwrap().to_string(),
+            continent: record.get(6).unwrap().to_string(),
+            status: record.get(7).unwrap().to_string(),
+        };
+        match parse_server(&unparsed) {
+            Ok(parsed) => out.push(parsed),
+            Err(err) => warnings.push(format!("Line {}: {}", line, err)),
+        }
+    }
+
+    if out.is_empty() {
+        return Err(mkerr("No servers found in the file (could not parse it)"));
+    }
+
+    Ok(out)
+}
+
+#[allow(clippy::needless_return)]
+fn servers_filename_has_content(filename: &str) -> bool {
+    let meta_result = std::fs::metadata(filename);
+    if meta_result.is_err() { return false; }
+    let len = meta_result.unwrap().len();
+    return len > 10;
+}
+
+// Whether any list we download has been
+fn servers_file_has_content() -> bool {
+    server_sources().iter().filter(|source| source.enabled && source.url.is_some()).any(|source| {
+        let filenames_result = source.filenames();
+        filenames_result.is_ok() && servers_filename_has_content(&filenames_result.unwrap().0)
+    })
+}
+
+// Every enabled list's servers, tagged with its name. A list we can't read is left out
+fn get_parsed_servers() -> std::io::Result<Vec<ParsedServer>> {
+    let sources: Vec<ServerSource> = server_sources().into_iter().filter(|source| source.enabled).collect();
+    let prefix = |source: &ServerSource| if sources.len() > 1 { format!("{}: ", source.name) } else { String::new() };
+    let mut out = Vec::new();
+    let mut errors = Vec::new();
+    for source in &sources {
+        let filenames_result = source.filenames();
+        if filenames_result.is_err() {
+            errors.push(format!("{}Could not get filename for servers", prefix(source)));
+            continue;
+        }
+
+        let filename = filenames_result.unwrap().0;
+        if !servers_filename_has_content(&filename) {
+            if source.url.is_some() { errors.push(format!("{}Please download servers first", prefix(source))); }
+            else { errors.push(format!("{}{} is missing or empty", prefix(source), filename)); }
+            continue;
+        }
+
+        let servers = match parse_source_file(&filename, source.is_json(), &mut Vec::new()) {
+            Ok(servers) => servers,
+            Err(err) => {
+                errors.push(format!("{}{}", prefix(source), err));
+                continue;
+            }
+        };
+        for mut server in servers {
+            server.source = source.name.clone();
+            out.push(server);
+        }
+    }
+
+    if out.is_empty() {
+        if errors.is_empty() { return Err(mkerr("Every server list is turned off in File > Server Lists")); }
+        return Err(mkerr(&errors.join("\n")));
+    }
+    for error in errors { log(&format!("get_parsed_servers: {}", error)); }
+    Ok(out)
+}
+
+// What we know about our copy of the list, kept next to it
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+struct ServersMeta {
+    etag: Option<String>,
+    last_modified: Option<String>,
+    downloaded: String,     // RFC 3339, when we last got a new list
+    checked: String,        // RFC 3339, when the site last said ours was current
+}
+
+impl ServersMeta {
+    // Since we last knew it was current
+    fn age(&self) -> Option<chrono::TimeDelta> {
+        let checked_result = chrono::DateTime::parse_from_rfc3339(&self.checked);
+        if checked_result.is_err() { return None; }
+        Some(chrono::Local::now().fixed_offset() - checked_result.unwrap())
+    }
+}
+
+fn get_servers_meta_filename() -> std::io::Result<String> {
+    get_config_filename("unparsed_servers.json")
+}
+
+fn load_servers_meta(filename: &str) -> ServersMeta {
+    let contents_result = std::fs::read_to_string(filename);
+    if contents_result.is_err() { return ServersMeta::default(); }
+    serde_json::from_str(&contents_result.unwrap()).unwrap_or_default()
+}
+
+// eg "3 days old"
+fn friendly_age(age: chrono::TimeDelta) -> String {
+    let plural = |count: i64, unit: &str| format!("{} {}{} old", count, unit, if count == 1 { "" } else { "s" });
+    if age.num_minutes() < 1 { return "under a minute old".to_string(); }
+    if age.num_hours() < 1 { return plural(age.num_minutes().max(0), "minute"); }
+    if age.num_hours() < 48 { return plural(age.num_hours(), "hour"); }
+    plural(age.num_days(), "day")
+}
+
+// For the menu, the oldest of the lists we download. None if we don't know
+fn servers_age() -> Option<chrono::TimeDelta> {
+    server_sources().iter()
+        .filter(|source| source.enabled && source.url.is_some())
+        .filter_map(|source| source.filenames().ok())
+        .filter_map(|(_, meta_filename)| load_servers_meta(&meta_filename).age())
+        .max()
+}
+
+// None if the site says what we have is current.
+// meta gets the new ETag and Last-Modified when there's a new list
+fn download_url_if_changed(url: &str, meta: &mut ServersMeta) -> Result<Option<String>, Box<dyn Error>> {
+    let client = reqwest::blocking::Client::builder().timeout(Duration::from_secs(20)).build()?;
+    let mut request = client.get(url);
+    if meta.etag.is_some() { request = request.header(reqwest::header::IF_NONE_MATCH, meta.etag.clone().unwrap()); }
+    if meta.last_modified.is_some() { request = request.header(reqwest::header::IF_MODIFIED_SINCE, meta.last_modified.clone().unwrap()); }
+    let response = request.send()?;
+
+    if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+        return Ok(None);
+    }
+    if !response.status().is_success() {
+        return Err(format!("Could not download: HTTP {}", response.status()).into());
+    }
+    let header = |name| response.headers().get(name).and_then(|value: &reqwest::header::HeaderValue| value.to_str().ok()).map(|value| value.to_string());
+    meta.etag = header(reqwest::header::ETAG);
+    meta.last_modified = header(reqwest::header::LAST_MODIFIED);
+    let body = response.text()?;
+    Ok(Some(body))
+}
+
+// Downloads the list if it has changed. A new list that we can't read doesn't replace the one
+// we have, and the one it replaces is kept as .bak. Returns what happened, for the status
+fn refresh_servers_file(url: &str, filename: &str, meta_filename: &str, json: bool) -> String {
+    let mut meta = load_servers_meta(meta_filename);
+    if !servers_filename_has_content(filename) { meta = ServersMeta::default(); }  // Nothing to compare with
+
+    let body_opt = match download_url_if_changed(url, &mut meta) {
+        Ok(body_opt) => body_opt,
+        Err(err) => return err.to_string(),
+    };
+    let now = chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, false);
+    let save_meta = |meta: &ServersMeta| {
+        if let Err(err) = save_file_contents(meta_filename, &serde_json::to_string_pretty(meta).unwrap()) {
+            log(&format!("refresh_servers_file: could not save {}: {}", meta_filename, err));
+        }
+    };
+
+    if body_opt.is_none() {
+        meta.checked = now;
+        save_meta(&meta);
+        let servers_result = parse_source_file(filename, json, &mut Vec::new());
+        let count = servers_result.map_or(0, |servers| servers.len());
+        return format!("The list of {} servers is up to date", count);
+    }
+
+    let new_filename = format!("{}.new", filename);
+    let save_result = save_file_contents(&new_filename, &body_opt.unwrap());
+    if save_result.is_err() {
+        return "Downloaded list of servers but could not save to a file - permission?".to_string();
+    }
+    let mut warnings = Vec::new();
+    let servers = match parse_source_file(&new_filename, json, &mut warnings) {
+        Ok(servers) => servers,
+        Err(err) => {
+            let _ = std::fs::remove_file(&new_filename);
+            return format!("Downloaded list of servers but {}, so kept the one we had", err);
+        }
+    };
+
+    if std::path::Path::new(filename).exists()
+        && let Err(err) = std::fs::rename(filename, format!("{}.bak", filename)) {
+        log(&format!("refresh_servers_file: could not back up {}: {}", filename, err));
+    }
+    if let Err(err) = std::fs::rename(&new_filename, filename) {
+        return format!("Downloaded list of servers but could not save it: {}", err);
+    }
+    meta.downloaded = now.clone();
+    meta.checked = now;
+    save_meta(&meta);
+    format!("Downloaded {} servers", servers.len()) + &skipped_rows_text(&warnings)
+}
+
+// Every enabled list we download, one status line each
+fn refresh_servers() -> String {
+    let sources: Vec<ServerSource> = server_sources().into_iter().filter(|source| source.enabled && source.url.is_some()).collect();
+    if sources.is_empty() { return "There are no server lists to download - see File > Server Lists".to_string(); }
+
+    let mut statuses = Vec::new();
+    for source in &sources {
+        let status = match source.filenames() {
+            Ok((filename, meta_filename)) => refresh_servers_file(source.url.as_ref().unwrap(), &filename, &meta_filename, source.is_json()),
+            Err(err) => err.to_string(),
+        };
+        log(&format!("refresh_servers: {}: {}", source.name, status.replace('\n', " - ")));
+        statuses.push(if sources.len() > 1 { format!("{}: {}", source.name, status) } else { status });
+    }
+    statuses.join("\n")
+}
+
+// With refresh_days in the config, gets a new list at launch when ours is that old
+fn background_refresh_servers(sink: &CbSink) {
+    let refresh_days = get_config().refresh_days;
+    if refresh_days == 0 || !servers_file_has_content() { return; }
+    let age_opt = servers_age();
+    if age_opt.is_some() && age_opt.unwrap() < chrono::TimeDelta::days(refresh_days as i64) { return; }
+
+    refresh_servers();
+    sink.send(Box::new(add_menu)).unwrap();    // For the new age
+}
+
+// eg "\nSkipped 2 rows:\nLine 12: no server address ...", empty if there weren't any
+fn skipped_rows_text(warnings: &[String]) -> String {
+    if warnings.is_empty() { return String::new(); }
+    const MAX_SHOWN: usize = 5;
+    for warning in warnings { log(&format!("servers file: {}", warning)); }
+    let mut out = format!("\nSkipped {} row{}:", warnings.len(), if warnings.len() == 1 { "" } else { "s" });
+    for warning in warnings.iter().take(MAX_SHOWN) { out += &format!("\n{}", warning); }
+    if warnings.len() > MAX_SHOWN { out += &format!("\n... and {} more, see the log", warnings.len() - MAX_SHOWN); }
+    out
+}
+
+// Doesn't return an error - but sets in the status
+fn download_servers(sink: &CbSink) {
+    let status = refresh_servers();
+    sink.send(Box::new(|s: &mut Cursive| {
+        s.call_on_name("status", |view: &mut NamedView<TextView> | view.get_mut().set_content(status));
+        add_menu(s);    // For the new age
+    })).unwrap();
+
+}
+
+//
+// Server list sources
+//
+
+const PUBLIC_SOURCE: &str = "public";  // servers_url
+
+// A list of servers in the config file, eg our own lab's
+//   [[sources]]
+//   name = "lab"
+//   url = "https://intranet.example.com/iperf3.csv"     # or path = "~/lab-servers.json"
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+struct ServerSource {
+    name: String,
+    url: Option<String>,
+    path: Option<String>,   // A local file, read where it is
+    enabled: bool,          // File > Server Lists can change it
+}
+
+impl Default for ServerSource {
+    fn default() -> Self {
+        ServerSource { name: String::new(), url: None, path: None, enabled: true }
+    }
+}
+
+impl ServerSource {
+    // Otherwise CSV with the same columns as the public list
+    fn is_json(&self) -> bool {
+        let location = self.path.clone().or(self.url.clone()).unwrap_or_default();
+        location.split(['?', '#']).next().unwrap_or("").to_lowercase().ends_with(".json")
+    }
+
+    // Where the list is, and where its ServersMeta is if we download it
+    fn filenames(&self) -> std::io::Result<(String, String)> {
+        if let Some(path) = &self.path { return Ok((expand_home(path), String::new())); }
+        if self.name == PUBLIC_SOURCE { return Ok((get_servers_filename()?, get_servers_meta_filename()?)); }
+
+        let safe_name: String = self.name.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' }).collect();
+        let extension = if self.is_json() { "json" } else { "csv" };
+        let filename = get_config_filename(&format!("source-{}.{}", safe_name, extension))?;
+        let meta_filename = get_config_filename(&format!("source-{}.meta.json", safe_name))?;
+        Ok((filename, meta_filename))
+    }
+}
+
+// eg ~/lab.csv -> /home/dave/lab.csv
+fn expand_home(path: &str) -> String {
+    if let Some(rest) = path.strip_prefix("~/") && let Some(home) = dirs::home_dir() { return home.join(rest).to_str().unwrap_or(path).to_string(); }
+    path.to_string()
+}
+
+// The public list then the config file's, turned on or off as in File > Server Lists
+fn server_sources() -> Vec<ServerSource> {
+    let config = get_config();
+    let mut out = vec![ServerSource { name: PUBLIC_SOURCE.to_string(), url: Some(config.servers_url.clone()), ..Default::default() }];
+    out.extend(config.sources);
+    let history = load_history();
+    for source in &mut out {
+        source.enabled = *history.sources_enabled.get(&source.name).unwrap_or(&source.enabled);
+    }
+    out
+}
+
+fn parse_source_file(filename: &str, json: bool, warnings: &mut Vec<String>) -> std::io::Result<Vec<ParsedServer>> {
+    if json { return parse_servers_json_file(filename, warnings); }
+    parse_servers_file(filename, warnings)
+}
+
+// The same as the CSV's columns, or just a host, eg
+//   [{"host": "iperf.lab.example.com", "port": "5201-5209", "site": "Lab 2"}]
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct JsonServer {
+    host: String,
+    port: String,
+    cmd: String,    // Instead of host and port, eg "iperf3 -c iperf.lab.example.com -p 5201"
+    options: String,
+    speed: String,
+    country: String,
+    site: String,
+    provider: String,
+    continent: String,
+    status: String,
+}
+
+fn parse_servers_json_file(filename: &str, warnings: &mut Vec<String>) -> std::io::Result<Vec<ParsedServer>> {
+    let contents = std::fs::read_to_string(filename)?;
+    let entries: Vec<JsonServer> = serde_json::from_str(&contents)
+        .map_err(|err| mkerr(&format!("Could not read the file as JSON: {}", err)))?;
+
+    let mut out: Vec<ParsedServer> = Vec::new();
+    for (index, entry) in entries.into_iter().enumerate() {
+        let unparsed = UnparsedServer {
+            cmd: if !entry.cmd.is_empty() { entry.cmd } else if !entry.host.is_empty() { format!("-c {}", entry.host) } else { String::new() },
+            options: if entry.port.is_empty() { entry.options } else { format!("{} -p {}", entry.options, entry.port) },
+            speed: entry.speed,
+            country: entry.country,
+            site: entry.site,
+            provider: entry.provider,
+            continent: entry.continent,
+            status: entry.status,
+        };
+        match parse_server(&unparsed) {
+            Ok(parsed) => out.push(parsed),
+            Err(err) => warnings.push(format!("Entry {}: {}", index + 1, err)),
+        }
+    }
+
+    if out.is_empty() {
+        return Err(mkerr("No servers found in the file (could not parse it)"));
+    }
+    Ok(out)
+}
+
+//
+// Favourites and recent servers
+//
+
+// A server with the options it was tested with
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+struct SavedServer {
+    args: Args,
+    last_used: String,  // RFC 3339
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+struct History {
+    favourites: Vec<SavedServer>,
+    recent: Vec<SavedServer>,   // Most recent first
+    start_last: bool,           // Run the most recent test at launch if there's no -c
+    working_ports: BTreeMap<String, u16>,   // Server to the port in its range that was free last time
+    sources_enabled: BTreeMap<String, bool>, // Server lists turned on or off in File > Server Lists
+    reachability: BTreeMap<String, Reachability>,  // Server to how our tests of it went
+}
+
+// Our own record of testing a server, as well as the status the list gives it
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+struct Reachability {
+    last_worked: String,    // RFC 3339, empty if never
+    last_failed: String,
+    worked: u32,
+    failed: u32,
+    latest_worked: bool,    // Both can be in the same second
+}
+
+impl Reachability {
+    // eg "✓3/4" - the last test worked, and 3 of the 4 we've run did. Empty if we haven't tested it
+    fn indicator(&self) -> String {
+        let total = self.worked + self.failed;
+        if total == 0 { return String::new(); }
+        let mark = if self.latest_worked { "✓" } else { "✗" };
+        format!("{}{}/{}", mark, self.worked, total)
+    }
+}
+
+impl History {
+    const MAX_RECENT: usize = 10;
+
+    fn add_recent(&mut self, args: &Args) {
+        self.recent.retain(|saved| !saved.args.same_test(args));
+        let last_used = chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, false);
+        let saved = SavedServer { args: args.clone(), last_used };
+        self.recent.insert(0, saved);
+        self.recent.truncate(History::MAX_RECENT);
+    }
+
+    fn record_test(&mut self, server: &str, worked: bool) {
+        let reachability = self.reachability.entry(server.to_string()).or_default();
+        let now = chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, false);
+        reachability.latest_worked = worked;
+        if worked {
+            reachability.last_worked = now;
+            reachability.worked += 1;
+        }
+        else {
+            reachability.last_failed = now;
+            reachability.failed += 1;
+        }
+    }
+
+    fn is_favourite(&self, args: &Args) -> bool {
+        self.favourites.iter().any(|saved| saved.args.same_test(args))
+    }
+
+    fn toggle_favourite(&mut self, args: &Args) {
+        if self.is_favourite(args) {
+            self.favourites.retain(|saved| !saved.args.same_test(args));
+            return;
+        }
+        let last_used = chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, false);
+        let saved = SavedServer { args: args.clone(), last_used };
+        self.favourites.push(saved);
+    }
+
+    // The test to start at launch, if the user wants that
+    fn last_to_start(&self) -> Option<Args> {
+        if !self.start_last { return None; }
+        self.recent.first().map(|saved| saved.args.clone())
+    }
+
+    // Rows for the top of Select Server - favourites, then recent tests that aren't favourites.
+    // We borrow the details from the list when the server is in it.
+    fn pinned_servers(&self, servers: &[ParsedServer]) -> Vec<ParsedServer> {
+        let mut out = Vec::new();
+        let recent = self.recent.iter().filter(|saved| !self.is_favourite(&saved.args));
+        let pinned = self.favourites.iter().map(|saved| ("★", saved)).chain(recent.map(|saved| ("↺", saved)));
+        for (marker, saved) in pinned {
+            let listed = servers.iter().find(|server| server.args.server == saved.args.server && server.args.ports == saved.args.ports);
+            let mut server = listed.cloned().unwrap_or_default();
+            if listed.is_none() { server.site = saved.args.get_server_as_string(); }
+            server.args = saved.args.clone();
+            server.marker = marker.to_string();
+            out.push(server);
+        }
+        out
+    }
+}
+
+// For reading. Something that can't be parsed is an empty history
+fn load_history() -> History {
+    read_history().unwrap_or_default()
+}
+
+// A missing file is an empty history, a broken one is an error
+fn read_history() -> Result<History, String> {
+    let filename = get_config_filename("history.json").map_err(|err| err.to_string())?;
+    let contents = match std::fs::read_to_string(filename) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(History::default()),
+        Err(err) => return Err(err.to_string()),
+    };
+    serde_json::from_str(&contents).map_err(|err| err.to_string())
+}
+
+// Written to a temporary file and renamed, so a reader never sees half of it
+fn save_history(history: &History) {
+    let filename_result = get_config_filename("history.json");
+    if filename_result.is_err() { return; }
+    let filename = filename_result.unwrap();
+    let temp_filename = format!("{}.{}.tmp", filename, process::id());
+    let save_result = save_file_contents(&temp_filename, &serde_json::to_string_pretty(history).unwrap())
+        .and_then(|_| std::fs::rename(&temp_filename, &filename));
+    if let Err(err) = save_result {
+        let _ = std::fs::remove_file(&temp_filename);
+        log(&format!("save_history: could not save {}: {}", filename, err));
+    }
+}
+
+// Dashboard panes and the main test can finish at the same time, so one change at a time.
+// If the file can't be read it's left alone rather than replaced with an empty history
+fn change_history(change: impl FnOnce(&mut History)) -> History {
+    let _guard = HISTORY_LOCK.lock().unwrap();
+    let mut history = match read_history() {
+        Ok(history) => history,
+        Err(err) => {
+            log(&format!("change_history: not changing history.json, could not read it: {}", err));
+            return History::default();
+        }
+    };
+    change(&mut history);
+    save_history(&history);
+    history
+}
+
+fn start_saved_server(args: &Args) {
+    log(&format!("start_saved_server: {}", args.friendly()));
+    save_args(args);
+    save_replay(None);
+    save_state(State::ReloadRequested);
+}
+
+//
+// Latency probe
+//
+
+// How long a TCP connect takes, or None if it fails
+fn probe_latency(host: &str, port: u16, ipv6: bool, timeout: Duration) -> Option<Duration> {
+    use std::net::ToSocketAddrs;
+    let addrs_result = (host, port).to_socket_addrs();
+    if addrs_result.is_err() { return None; }
+    // Prefer the family iperf3 will use, but a host with only the other one still answers
+    let addrs: Vec<std::net::SocketAddr> = addrs_result.unwrap().collect();
+    let addr = addrs.iter().find(|addr| addr.is_ipv6() == ipv6).or(addrs.first())?;
+
+    let start = std::time::Instant::now();
+    let result = std::net::TcpStream::connect_timeout(addr, timeout);
+    if result.is_err() { return None; }
+    Some(start.elapsed())
+}
+
+fn get_latency(server: &ParsedServer) -> Option<Option<Duration>> {
+    let latencies = LATENCIES.lock().unwrap();
+    latencies.get(&server.latency_key()).cloned()
+}
+
+fn friendly_latency(latency_opt: Option<Option<Duration>>) -> String {
+    match latency_opt {
+        None => "...".to_string(),  // Not probed yet
+        Some(None) => "fail".to_string(),
+        Some(Some(latency)) => format!("{} ms", latency.as_millis()),
+    }
+}
+
+// Probes every server not already probed, several at a time, refreshing the picker as results arrive
+fn start_latency_probe(sink: CbSink, servers: Vec<ParsedServer>) {
+    if PROBING.swap(true, Ordering::SeqCst) { return; }    // Already running
+
+    let todo: Vec<ParsedServer> = servers.into_iter().filter(|server| get_latency(server).is_none()).collect();
+    let queue = Arc::new(Mutex::new(todo));
+    std::thread::spawn(move || {
+        let mut workers = Vec::new();
+        for _ in 0..16 {
+            let queue = queue.clone();
+            workers.push(std::thread::spawn(move || {
+                loop {
+                    let server_opt = queue.lock().unwrap().pop();
+                    if server_opt.is_none() { return; }
+                    let server = server_opt.unwrap();
+                    let latency = probe_latency(&server.args.get_server_as_string(), server.control_port(), server.args.ipv6, Duration::from_secs(2));
+                    LATENCIES.lock().unwrap().insert(server.latency_key(), latency);
+                }
+            }));
+        }
+
+        while workers.iter().any(|worker| !worker.is_finished()) {
+            std::thread::sleep(Duration::from_millis(500));
+            let _ = sink.send(Box::new(|s: &mut Cursive| refresh_server_list(s)));
+        }
+        PROBING.store(false, Ordering::SeqCst);
+        let _ = sink.send(Box::new(|s: &mut Cursive| refresh_server_list(s)));
+    });
+}
+
+//
+//  Dialogs
+//
+
+fn download_servers_dialog(siv: &mut Cursive) {
+    let sink = siv.cb_sink().clone();
+    siv.add_layer(
+        Dialog::new()
+        .title("Download list of iperf3 servers")
+        .padding_lrtb(1, 1, 1, 0)
+        .content(
+            TextView::new("Downloading...")
+                .with_name("status")
+                .fixed_width(50),
+        )
+        .button("Close", |s| { s.pop_layer(); })
+    );
+
+    std::thread::spawn(move || { download_servers(&sink) });
+}
+
+#[allow(clippy::unnecessary_unwrap)]
+fn select_server_dialog(siv: &mut Cursive) {
+    let servers_result = get_parsed_servers();
+    if servers_result.is_err() {
+        siv.add_layer(
+            Dialog::new()
+            .title("Select an iperf3 server")
+            .padding_lrtb(1, 1, 1, 0)
+            .content(TextView::new(servers_result.unwrap_err().to_string()))
+            .button("Close", |s| { s.pop_layer(); }));
+        return;
+    }
+    let history = load_history();
+    let mut servers = history.pinned_servers(servers_result.as_ref().unwrap());
+    servers.extend(servers_result.unwrap());
+    for server in &mut servers {
+        server.reachability = history.reachability.get(&server.args.get_server_as_string()).cloned().unwrap_or_default();
+    }
+
+    let select = SelectView::<ParsedServer>::new()
+        .on_submit(|s, item| {
+            save_args(&item.args.with_defaults(&get_config().defaults));
+            save_replay(None);
+            save_state(State::ReloadRequested);
+            log(&format!("select_server_dialog: user selected {} with {}", item.friendly(), item.args.friendly()));
+            s.pop_layer();
+        });
+
+    let mut continents: Vec<String> = servers.iter().map(|server| server.continent.clone()).filter(|continent| has_content_string(continent)).collect();
+    continents.sort();
+    continents.dedup();
+
+    let widths = column_widths(&servers);
+    {
+        let mut picker = SERVER_PICKER.lock().unwrap();
+        let picked = std::mem::take(&mut picker.picked);    // Kept for Benchmark Servers
+        *picker = ServerPicker::default();
+        picker.picked = picked;
+        picker.servers = servers.clone();
+        picker.widths = widths.clone();
+    }
+
+    // Headers are buttons - pressing one sorts by that column, again to reverse
+    let mut header = LinearLayout::horizontal().child(TextView::new("  "));   // Above the markers
+    for (index, column) in COLUMNS.iter().enumerate() {
+        let column = *column;
+        header.add_child(TextView::new(COLUMN_GAP));
+        header.add_child(Button::new_raw(column.header(None, widths[index]), move |s| sort_server_list(s, column)).with_name(format!("header_{}", index)));
+    }
+
+    let mut continent_select = SelectView::<Option<String>>::new()
+        .popup()
+        .item("All continents", None)
+        .on_submit(|s, continent: &Option<String>| {
+            SERVER_PICKER.lock().unwrap().continent = continent.clone();
+            refresh_server_list(s);
+        });
+    for continent in continents {
+        continent_select.add_item(continent.clone(), Some(continent));
+    }
+
+    let filters = LinearLayout::horizontal()
+        .child(TextView::new("Filter: "))
+        .child(EditView::new()
+            .on_edit(|s, text, _| {
+                SERVER_PICKER.lock().unwrap().filter = text.to_string();
+                refresh_server_list(s);
+            })
+            .min_width(25))
+        .child(TextView::new("  "))
+        .child(continent_select)
+        .child(TextView::new("  IPv6 only "))
+        .child(Checkbox::new().on_change(|s, checked| {
+            SERVER_PICKER.lock().unwrap().ipv6_only = checked;
+            refresh_server_list(s);
+        }))
+        .child(TextView::new("  Show offline "))
+        .child(Checkbox::new().on_change(|s, checked| {
+            SERVER_PICKER.lock().unwrap().show_offline = checked;
+            refresh_server_list(s);
+        }));
+
+    let layout = LinearLayout::vertical()
+        .child(filters)
+        .child(TextView::new("").with_name("server_count"))
+        .child(header)
+        .child(select.with_name("servers").scrollable());
+
+    siv.add_layer(Dialog::around(layout)
+        .title("Select an iperf3 server")
+        .button("Favourite on/off", toggle_favourite_server)
+        .button("Pick for benchmark", toggle_picked_server)
+        .button("Cancel", |s| { s.pop_layer(); } )
+    );
+    refresh_server_list(siv);
+
+    start_latency_probe(siv.cb_sink().clone(), servers);
+}
+
+// The columns of the Select Server table
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Column {
+    Latency,
+    Continent,
+    Country,
+    Site,
+    Provider,
+    Speed,
+    Ports,
+    Status,
+    Source,
+}
+
+const COLUMNS: [Column; 9] = [Column::Latency, Column::Continent, Column::Country, Column::Site, Column::Provider, Column::Speed, Column::Ports, Column::Status, Column::Source];
+const COLUMN_GAP: &str = "  ";
+
+impl Column {
+    fn title(&self) -> &'static str {
+        match self {
+            Column::Latency => "Latency",
+            Column::Continent => "Continent",
+            Column::Country => "Country",
+            Column::Site => "Site",
+            Column::Provider => "Provider",
+            Column::Speed => "Gbit/s",
+            Column::Ports => "Ports",
+            Column::Status => "Status",
+            Column::Source => "List",
+        }
+    }
+
+    fn text(&self, server: &ParsedServer) -> String {
+        match self {
+            Column::Latency => friendly_latency(get_latency(server)),
+            Column::Continent => server.continent.clone(),
+            Column::Country => server.country.clone(),
+            Column::Site => server.site.clone(),
+            Column::Provider => server.provider.clone(),
+            Column::Speed => server.speed.clone(),
+            Column::Ports => server.args.get_ports(),
+            Column::Status => format!("{} {}", server.status, server.reachability.indicator()).trim().to_string(),
+            Column::Source => server.source.clone(),
+        }
+    }
+
+    fn is_numeric(&self) -> bool {
+        *self == Column::Latency || *self == Column::Speed
+    }
+
+    fn compare(&self, a: &ParsedServer, b: &ParsedServer) -> std::cmp::Ordering {
+        match self {
+            Column::Latency => latency_order(a).cmp(&latency_order(b)),
+            Column::Speed => {
+                // Servers without a speed go last
+                let a_speed = speed_value(&a.speed).unwrap_or(f64::MIN);
+                let b_speed = speed_value(&b.speed).unwrap_or(f64::MIN);
+                b_speed.total_cmp(&a_speed)    // Fastest first
+            }
+            Column::Ports => first_port(&a.args.get_ports()).cmp(&first_port(&b.args.get_ports())),
+            _ => self.text(a).to_lowercase().cmp(&self.text(b).to_lowercase()),
+        }
+    }
+
+    fn header(&self, sort: Option<(Column, bool)>, width: usize) -> String {
+        let descending_opt = sort.filter(|(column, _)| column == self).map(|(_, descending)| descending);
+        sort_header(self.title(), descending_opt, width, self.is_numeric())
+    }
+}
+
+// eg "Country ▲", with an arrow if the table is sorted by this column
+fn sort_header(title: &str, descending_opt: Option<bool>, width: usize, right_align: bool) -> String {
+    let mut title = title.to_string();
+    if descending_opt == Some(false) { title += " ▲"; }
+    if descending_opt == Some(true) { title += " ▼"; }
+    fit(&title, width, right_align)
+}
+
+// The list's status, eg "OK" or "Offline"
+fn is_offline(status: &str) -> bool {
+    let status = status.trim().to_lowercase();
+    status.contains("offline") || status == "down" || status.contains("unreachable")
+}
+
+// eg "10" -> 10.0, "2x100" -> 2.0
+fn speed_value(speed: &str) -> Option<f64> {
+    let number: String = speed.trim().chars().take_while(|c| c.is_ascii_digit() || *c == '.').collect();
+    number.parse::<f64>().ok()
+}
+
+// Pads or truncates to exactly width characters
+fn fit(text: &str, width: usize, right_align: bool) -> String {
+    let count = text.chars().count();
+    if count > width {
+        return text.chars().take(width.saturating_sub(1)).collect::<String>() + "…";
+    }
+    let padding = " ".repeat(width - count);
+    if right_align { return padding + text; }
+    text.to_string() + &padding
+}
+
+// Wide enough for the title and the data, but not too wide
+fn column_widths(servers: &Vec<ParsedServer>) -> Vec<usize> {
+    let mut out = Vec::new();
+    for column in COLUMNS {
+        let mut width = column.title().chars().count() + 2;   // Room for the sort arrow
+        if column == Column::Latency { width = width.max(8); }
+        for server in servers {
+            width = width.max(column.text(server).chars().count());
+        }
+        out.push(width.min(18));
+    }
+    out
+}
+
+fn server_row(server: &ParsedServer, widths: &Vec<usize>, picked: bool) -> String {
+    let mut cells: Vec<String> = vec![fit(&server.marker, 1, false) + if picked { "✓" } else { " " }];
+    cells.extend(COLUMNS.iter().zip(widths).map(|(column, width)| fit(&column.text(server), *width, column.is_numeric())));
+    cells.join(COLUMN_GAP)
+}
+
+// Picks the selected server for File > Benchmark Servers, or unpicks it
+fn toggle_picked_server(siv: &mut Cursive) {
+    let selected = siv.call_on_name("servers", |view: &mut SelectView<ParsedServer>| view.selection()).flatten();
+    if selected.is_none() { return; }
+    let selected = selected.unwrap();
+    {
+        let mut picker = SERVER_PICKER.lock().unwrap();
+        if picker.is_picked(&selected) {
+            picker.picked.retain(|server| server.args != selected.args);
+        }
+        else {
+            let mut server = (*selected).clone();
+            server.marker = String::new();
+            picker.picked.push(server);
+        }
+    }
+    refresh_server_list(siv);
+}
+
+// Favourites the selected server, or unfavourites it, and moves it in the list to match
+fn toggle_favourite_server(siv: &mut Cursive) {
+    let selected = siv.call_on_name("servers", |view: &mut SelectView<ParsedServer>| view.selection()).flatten();
+    if selected.is_none() { return; }
+    let selected = selected.unwrap();
+
+    let history = change_history(|history| history.toggle_favourite(&selected.args));
+    {
+        let mut picker = SERVER_PICKER.lock().unwrap();
+        let listed: Vec<ParsedServer> = picker.servers.iter().filter(|server| server.marker.is_empty()).cloned().collect();
+        picker.servers = history.pinned_servers(&listed);
+        picker.servers.extend(listed);
+    }
+    refresh_server_list(siv);
+    add_menu(siv);
+}
+
+fn sort_server_list(siv: &mut Cursive, column: Column) {
+    {
+        let mut picker = SERVER_PICKER.lock().unwrap();
+        let descending = picker.sort == Some((column, false));
+        picker.sort = Some((column, descending));
+    }
+    refresh_server_list(siv);
+}
+
+// What the Select Server dialog is showing
+#[derive(Default)]
+struct ServerPicker {
+    servers: Vec<ParsedServer>,
+    widths: Vec<usize>,
+    sort: Option<(Column, bool)>,   // The column and whether it is reversed. None is the list order
+    filter: String,             // Words to look for in the continent, country, site, provider and list
+    continent: Option<String>,  // None is all
+    ipv6_only: bool,
+    show_offline: bool,         // The list says they're offline
+    picked: Vec<ParsedServer>,  // For File > Benchmark Servers
+}
+
+impl ServerPicker {
+    fn is_picked(&self, server: &ParsedServer) -> bool {
+        self.picked.iter().any(|picked| picked.args == server.args)
+    }
+
+    fn matches(&self, server: &ParsedServer) -> bool {
+        if self.ipv6_only && !server.ipv6_capable { return false; }
+        if !self.show_offline && server.marker.is_empty() && is_offline(&server.status) { return false; }   // Favourites and recents stay
+        if self.continent.is_some() && self.continent.as_ref() != Some(&server.continent) { return false; }
+
+        let haystack = format!("{} {} {} {} {}", server.continent, server.country, server.site, server.provider, server.source).to_lowercase();
+        self.filter.to_lowercase().split_whitespace().all(|word| haystack.contains(word))
+    }
+
+    // The servers to show, in order. Favourites and recent tests stay at the top
+    fn visible(&self) -> Vec<ParsedServer> {
+        let (mut out, mut listed): (Vec<ParsedServer>, Vec<ParsedServer>) = self.servers.iter().filter(|server| self.matches(server)).cloned().partition(|server| !server.marker.is_empty());
+        if let Some((column, descending)) = self.sort {
+            listed.sort_by(|a, b| column.compare(a, b));
+            if descending { listed.reverse(); }
+        }
+        out.extend(listed);
+        out
+    }
+}
+
+// Servers that answered come first, fastest first, then the ones we are waiting for, then failures
+fn latency_order(server: &ParsedServer) -> (u8, Duration) {
+    match get_latency(server) {
+        Some(Some(latency)) => (0, latency),
+        None => (1, Duration::ZERO),
+        Some(None) => (2, Duration::ZERO),
+    }
+}
+
+// Rebuilds the list from SERVER_PICKER, keeping the selection
+fn refresh_server_list(siv: &mut Cursive) {
+    let servers;
+    let widths;
+    let sort;
+    let total;
+    let offline;
+    let picked: Vec<bool>;
+    {
+        let picker = SERVER_PICKER.lock().unwrap();
+        servers = picker.visible();
+        picked = servers.iter().map(|server| picker.is_picked(server)).collect();
+        widths = picker.widths.clone();
+        sort = picker.sort;
+        total = picker.servers.len();
+        offline = if picker.show_offline { 0 } else { picker.servers.iter().filter(|server| server.marker.is_empty() && is_offline(&server.status)).count() };
+    }
+    let count = servers.len();
+
+    for (index, column) in COLUMNS.iter().enumerate() {
+        let label = column.header(sort, widths[index]);
+        siv.call_on_name(&format!("header_{}", index), |view: &mut Button| view.set_label_raw(label));
+    }
+
+    siv.call_on_name("servers", |view: &mut SelectView<ParsedServer>| {
+        let selected_key = view.selection().map(|server| server.marker.clone() + &server.latency_key() + &server.friendly());
+        view.clear();
+        let mut selected_index = 0;
+        for (index, server) in servers.into_iter().enumerate() {
+            if selected_key == Some(server.marker.clone() + &server.latency_key() + &server.friendly()) { selected_index = index; }
+            view.add_item(server_row(&server, &widths, picked[index]), server);
+        }
+        let _ = view.set_selection(selected_index);
+    });
+    let picked_count = SERVER_PICKER.lock().unwrap().picked.len();
+    let mut status = format!("{} of {} servers", count, total);
+    if offline > 0 { status += &format!(" ({} offline hidden)", offline); }
+    if picked_count > 0 { status += &format!(", {} picked for File > Benchmark Servers", picked_count); }
+    siv.call_on_name("server_count", |view: &mut TextView| view.set_content(status));
+}
+
+fn enter_server_dialog(siv: &mut Cursive) {
+    log("enter_server_dialog: start");
+    let defaults = get_config().defaults;   // Start with the config file's settings
+    let table = LinearLayout::vertical()
+        .child(
+            LinearLayout::horizontal()
+                .child(TextView::new("IPv6:").min_width(20))
+                .child(Checkbox::new().with_checked(defaults.ipv6).with_name("ipv6"))
+        )
+        .child(
+            LinearLayout::horizontal()
+                .child(TextView::new("Port Range:").min_width(20))
+                .child(EditView::new().content(defaults.get_ports()).with_name("ports").min_width(25))
+        )
+        .child(
+            LinearLayout::horizontal()
+                .child(TextView::new("Parallel Streams:").min_width(20))
+                .child(EditView::new().content(defaults.parallel.map(|parallel| parallel.to_string()).unwrap_or_default()).with_name("parallel").min_width(5))
+        )
+        .child(
+            LinearLayout::horizontal()
+                .child(TextView::new("Reverse:").min_width(20))
+                .child(Checkbox::new().with_checked(defaults.reverse).with_name("reverse"))
+        )
+        .child(
+            LinearLayout::horizontal()
+                .child(TextView::new("Time (seconds):").min_width(20))
+                .child(EditView::new().content(defaults.time.map(|time| time.to_string()).unwrap_or_default()).with_name("time").min_width(8))
+        )
+        .child(
+            LinearLayout::horizontal()
+                .child(TextView::new("UDP:").min_width(20))
+                .child(Checkbox::new().with_checked(defaults.udp).with_name("udp"))
+        )
+        .child(
+            LinearLayout::horizontal()
+                .child(TextView::new("Bidirectional:").min_width(20))
+                .child(Checkbox::new().with_checked(defaults.bidir).with_name("bidir"))
+        )
+        .child(
+            LinearLayout::horizontal()
+                .child(TextView::new("Target Bitrate:").min_width(20))
+                .child(EditView::new().content(defaults.bitrate.clone().unwrap_or_default()).with_name("bitrate").min_width(10))
+        )
+        .child(
+            LinearLayout::horizontal()
+                .child(TextView::new("Window Size:").min_width(20))
+                .child(EditView::new().content(defaults.window.clone().unwrap_or_default()).with_name("window").min_width(10))
+        )
+        .child(
+            LinearLayout::horizontal()
+                .child(TextView::new("MSS:").min_width(20))
+                .child(EditView::new().content(defaults.mss.map(|mss| mss.to_string()).unwrap_or_default()).with_name("mss").min_width(8))
+        )
+        .child(
+            LinearLayout::horizontal()
+                .child(TextView::new("Buffer Length:").min_width(20))
+                .child(EditView::new().content(defaults.length.clone().unwrap_or_default()).with_name("length").min_width(10))
+        )
+        .child(
+            LinearLayout::horizontal()
+                .child(TextView::new("Omit (seconds):").min_width(20))
+                .child(EditView::new().content(defaults.omit.map(|omit| omit.to_string()).unwrap_or_default()).with_name("omit").min_width(8))
+        )
+        .child(
+            LinearLayout::horizontal()
+                .child(TextView::new("Zero Copy:").min_width(20))
+                .child(Checkbox::new().with_checked(defaults.zerocopy).with_name("zerocopy"))
+        )
+        .child(
+            LinearLayout::horizontal()
+                .child(TextView::new("Congestion:").min_width(20))
+                .child(EditView::new().content(defaults.congestion.clone().unwrap_or_default()).with_name("congestion").min_width(16))
+        )
+        .child(
+            LinearLayout::horizontal()
+                .child(TextView::new("TOS:").min_width(20))
+                .child(EditView::new().content(defaults.tos.map(|tos| format!("{:#04x}", tos)).unwrap_or_default()).with_name("tos").min_width(8))
+        )
+        .child(
+            LinearLayout::horizontal()
+                .child(TextView::new("Bind Address:").min_width(20))
+                .child(EditView::new().content(defaults.bind.clone().unwrap_or_default()).with_name("bind").min_width(25))
+        )
+        .child(
+            LinearLayout::horizontal()
+                .child(TextView::new("CPU Affinity:").min_width(20))
+                .child(EditView::new().content(defaults.affinity.clone().unwrap_or_default()).with_name("affinity").min_width(8))
+        )
+        .child(
+            LinearLayout::horizontal()
+                .child(TextView::new("Server:").min_width(20))
+                .child(EditView::new().content(defaults.get_server_as_string()).with_name("server").min_width(50))
+        );
+    siv.add_layer(
+        Dialog::new()
+        .title("Enter iperf3 server details")
+        .padding_lrtb(1, 1, 1, 0)
+        .content(table)
+        .button("OK", |s| {
+            log("pressed OK");
+            let ipv6 = s.call_on_name("ipv6", |view: &mut Checkbox| view.is_checked()).unwrap_or_default();
+            log(&format!("ipv6={ipv6}"));
+            log("here");
+            let ports_arc = s.call_on_name("ports", |view: &mut EditView| view.get_content()).unwrap();
+            let ports_str = ports_arc.to_string();
+            log("got ports");
+            log(&format!("ports_arc={}", ports_arc).to_string());
+            log(&format!("ports_str={}", ports_str).to_string());
+            let parallel_str = s.call_on_name("parallel", |view: &mut EditView| view.get_content()).unwrap().to_string();
+            let reverse = s.call_on_name("reverse", |view: &mut Checkbox| view.is_checked()).unwrap_or_default();
+            let time_str = s.call_on_name("time", |view: &mut EditView| view.get_content()).unwrap().to_string();
+            let udp = s.call_on_name("udp", |view: &mut Checkbox| view.is_checked()).unwrap_or_default();
+            let bidir = s.call_on_name("bidir", |view: &mut Checkbox| view.is_checked()).unwrap_or_default();
+            if reverse && bidir {
+                s.add_layer(Dialog::info("Choose Reverse or Bidirectional, not both"));
+                return;
+            }
+            let server_arc = s.call_on_name("server", |view: &mut EditView| view.get_content()).unwrap();
+            let server_str = server_arc.to_string();
+            log("got values");
+            let mut args = Args::default();
+            if ipv6 { args.ipv6 = ipv6; }
+            if has_content_string(&ports_str) { args.ports = Some(ports_str.to_string()); }
+            if has_content_string(&parallel_str) {
+                let parallel_result = parallel_str.trim().parse::<u32>();
+                if parallel_result.is_err() || !(1..=128).contains(parallel_result.as_ref().unwrap()) {
+                    s.add_layer(Dialog::info("Parallel Streams must be a number from 1 to 128"));
+                    return;
+                }
+                args.parallel = Some(parallel_result.unwrap());
+            }
+            if reverse { args.reverse = reverse; }
+            if has_content_string(&time_str) {
+                let time_result = time_str.trim().parse::<u32>();
+                if time_result.is_err() {
+                    s.add_layer(Dialog::info("Time must be a number of seconds, 0 is forever"));
+                    return;
+                }
+                args.time = Some(time_result.unwrap());
+            }
+            if udp { args.udp = udp; }
+            if bidir { args.bidir = bidir; }
+
+            // The rest are checked the same way as on the command line
+            let options_result = (|| -> Result<(), String> {
+                args.bitrate = dialog_option(s, "bitrate", parse_bitrate_arg)?;
+                args.window = dialog_option(s, "window", parse_size_arg)?;
+                args.mss = dialog_option(s, "mss", parse_mss_arg)?;
+                args.length = dialog_option(s, "length", parse_size_arg)?;
+                args.omit = dialog_option(s, "omit", parse_omit_arg)?;
+                args.congestion = dialog_option(s, "congestion", parse_congestion_arg)?;
+                args.tos = dialog_option(s, "tos", parse_tos_arg)?;
+                args.bind = dialog_option(s, "bind", parse_bind_arg)?;
+                args.affinity = dialog_option(s, "affinity", parse_affinity_arg)?;
+                Ok(())
+            })();
+            if let Err(err) = options_result {
+                s.add_layer(Dialog::info(err));
+                return;
+            }
+            args.zerocopy = s.call_on_name("zerocopy", |view: &mut Checkbox| view.is_checked()).unwrap_or_default();
+            if has_content_string(&server_str) { args.server = Some(server_str.to_string()); }
+            // Unticking a box beats the config file, like --no-* on the command line,
+            // and what the dialog doesn't show (eg --reconnect) still comes from there
+            args.no_ipv6 = !args.ipv6;
+            args.no_reverse = !args.reverse;
+            args.no_udp = !args.udp;
+            args.no_bidir = !args.bidir;
+            args.no_zerocopy = !args.zerocopy;
+            let args = args.with_defaults(&get_config().defaults);
+            log(&format!("enter_server_dialog: server={}", server_str).to_string());
+            log(&format!("enter_server_dialog: user entered {}", args.friendly()).to_string());
+            save_args(&args.clone());
+            save_replay(None);
+            save_state(State::ReloadRequested);
+            s.pop_layer();
+        })
+        .button("Cancel", |s| { s.pop_layer(); })
+    );
+}
+
+// An EditView that can be left empty
+fn dialog_option<T>(siv: &mut Cursive, name: &str, parse: fn(&str) -> Result<T, String>) -> Result<Option<T>, String> {
+    let content = siv.call_on_name(name, |view: &mut EditView| view.get_content()).unwrap().to_string();
+    if !has_content_string(&content) { return Ok(None); }
+    parse(&content).map(Some)
+}
+
+fn record_dialog(siv: &mut Cursive) {
+    if let Some(recording) = get_recording() {
+        save_recording(None);
+        siv.add_layer(Dialog::info(format!("Stopped recording to\n{}", recording)).title("Record"));
+        return;
+    }
+
+    let filename = match get_new_recording_filename() {
+        Ok(filename) => filename,
+        Err(err) => {
+            siv.add_layer(Dialog::info(format!("Could not create the recordings folder: {}", err)).title("Record"));
+            return;
+        }
+    };
+    log(&format!("record_dialog: recording to {}", filename));
+    save_recording(Some(filename.clone()));
+    siv.add_layer(Dialog::info(format!("Recording every interval to\n{}", filename)).title("Record"));
+}
+
+fn open_recording_dialog(siv: &mut Cursive) {
+    let mut filenames = Vec::new();
+    let entries_result = get_recordings_dir().and_then(std::fs::read_dir);
+    if let Ok(entries) = entries_result {
+        for entry in entries.flatten() {
+            filenames.push(entry.path().to_str().unwrap().to_string());
+        }
+    }
+
+    if filenames.is_empty() {
+        siv.add_layer(Dialog::info("No recordings yet - use File > Record on/off or --record").title("Open Recording"));
+        return;
+    }
+    filenames.sort();
+    filenames.reverse();    // Newest first
+
+    let mut select = SelectView::<String>::new()
+        .on_submit(|s, filename: &String| {
+            log(&format!("open_recording_dialog: user selected {}", filename));
+            save_replay(Some(filename.clone()));
+            change_replay_control(|control| *control = ReplayControl::default());
+            save_state(State::ReloadRequested);
+            s.pop_layer();
+        });
+
+    for filename in filenames {
+        let basename = std::path::Path::new(&filename).file_name().unwrap().to_string_lossy().to_string();
+        select.add_item(basename, filename);
+    }
+
+    siv.add_layer(Dialog::around(select.scrollable())
+        .title("Open Recording")
+        .button("Cancel", |s| { s.pop_layer(); } )
+    );
+}
+
+fn summary_text(args: &Args, summary: &Vec<SummaryLine>, stats: &Stats) -> String {
+    let mut out = format!("{}\n{}\n\n", args.friendly(), chrono::Local::now().format("%Y-%m-%d %H:%M:%S"));
+    for line in summary {
+        out += &line.friendly();
+        out += "\n";
+    }
+    if !stats.is_empty() {
+        out += &format!("\nIntervals: {}\n", stats.friendly());
+    }
+    out
+}
+
+fn save_summary(text: &str) -> std::io::Result<String> {
+    let basename = chrono::Local::now().format("%Y-%m-%d_%H-%M-%S.txt").to_string();
+    let abs_filename = get_data_dir("summaries")?.join(basename);
+    let filename = abs_filename.to_str().unwrap().to_string();
+    save_file_contents(&filename, text)?;
+    Ok(filename)
+}
+
+fn summary_dialog(siv: &mut Cursive, text: String) {
+    let text_to_save = text.clone();
+    siv.add_layer(
+        Dialog::new()
+        .title("Test finished")
+        .padding_lrtb(1, 1, 1, 0)
+        .content(TextView::new(text))
+        .button("Save", move |s| {
+            let status = match save_summary(&text_to_save) {
+                Ok(filename) => format!("Saved to\n{}", filename),
+                Err(err) => format!("Could not save: {}", err),
+            };
+            s.add_layer(Dialog::info(status));
+        })
+        .button("Rerun", |s| {
+            save_state(State::ReloadRequested);
+            s.pop_layer();
+        })
+        .button("Close", |s| { s.pop_layer(); })
+    );
+}
+
+fn benchmark_dialog(siv: &mut Cursive) {
+    let servers_result = get_parsed_servers();
+    let servers = servers_result.unwrap_or_default();
+    let favourites: Vec<ParsedServer> = load_history().pinned_servers(&servers).into_iter().filter(|server| server.marker == "★").collect();
+    let picked = SERVER_PICKER.lock().unwrap().picked.clone();
+
+    let mut sources = SelectView::<Vec<ParsedServer>>::new();
+    if !picked.is_empty() { sources.add_item(format!("Picked in Select Server ({})", picked.len()), picked); }
+    if !favourites.is_empty() { sources.add_item(format!("Favourites ({})", favourites.len()), favourites); }
+    let mut continents: Vec<String> = servers.iter().map(|server| server.continent.clone()).filter(|continent| has_content_string(continent)).collect();
+    continents.sort();
+    continents.dedup();
+    for continent in continents {
+        let in_continent: Vec<ParsedServer> = servers.iter().filter(|server| server.continent == continent && !is_offline(&server.status)).cloned().collect();
+        if in_continent.is_empty() { continue; }
+        sources.add_item(format!("Everything in {} ({})", continent, in_continent.len()), in_continent);
+    }
+    if sources.is_empty() {
+        siv.add_layer(Dialog::info("Nothing to benchmark yet.\nDownload the list of servers, add favourites,\nor pick servers in Select Server").title("Benchmark Servers"));
+        return;
+    }
+
+    let layout = LinearLayout::vertical()
+        .child(TextView::new("Test each of:"))
+        .child(sources.with_name("benchmark_sources").scrollable().max_height(12))
+        .child(TextView::new(""))
+        .child(LinearLayout::horizontal()
+            .child(TextView::new("Seconds each: "))
+            .child(EditView::new().content("10").with_name("benchmark_seconds").min_width(6)));
+
+    siv.add_layer(
+        Dialog::around(layout)
+        .title("Benchmark Servers")
+        .padding_lrtb(1, 1, 1, 0)
+        .button("Start", |s| {
+            let servers_opt = s.call_on_name("benchmark_sources", |view: &mut SelectView<Vec<ParsedServer>>| view.selection()).flatten();
+            let seconds_str = s.call_on_name("benchmark_seconds", |view: &mut EditView| view.get_content()).unwrap().to_string();
+            let seconds_result = seconds_str.trim().parse::<u32>();
+            if seconds_result.is_err() || !(1..=3600).contains(seconds_result.as_ref().unwrap()) {
+                s.add_layer(Dialog::info("Seconds each must be a number from 1 to 3600"));
+                return;
+            }
+            if servers_opt.is_none() { return; }
+            let servers = (*servers_opt.unwrap()).clone();
+            log(&format!("benchmark_dialog: {} servers", servers.len()));
+            save_benchmark(Some(Benchmark { servers, seconds: seconds_result.unwrap() }));
+            save_replay(None);
+            save_state(State::ReloadRequested);
+            s.pop_layer();
+        })
+        .button("Cancel", |s| { s.pop_layer(); })
+    );
+}
+
+// The columns of the benchmark results
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum BenchmarkColumn {
+    Site,
+    Provider,
+    Host,
+    Mean,
+    Peak,
+    Failure,
+}
+
+const BENCHMARK_COLUMNS: [BenchmarkColumn; 6] = [BenchmarkColumn::Site, BenchmarkColumn::Provider, BenchmarkColumn::Host, BenchmarkColumn::Mean, BenchmarkColumn::Peak, BenchmarkColumn::Failure];
+
+impl BenchmarkColumn {
+    fn title(&self) -> &'static str {
+        match self {
+            BenchmarkColumn::Site => "Site",
+            BenchmarkColumn::Provider => "Provider",
+            BenchmarkColumn::Host => "Host",
+            BenchmarkColumn::Mean => "Mean",
+            BenchmarkColumn::Peak => "Peak",
+            BenchmarkColumn::Failure => "Failure",
+        }
+    }
+
+    fn text(&self, result: &BenchmarkResult) -> String {
+        match self {
+            BenchmarkColumn::Site => result.server.site.clone(),
+            BenchmarkColumn::Provider => result.server.provider.clone(),
+            BenchmarkColumn::Host => result.server.args.get_server_as_string(),
+            BenchmarkColumn::Mean => result.mean.map(|mean| format!("{:.1}", mean)).unwrap_or_default(),
+            BenchmarkColumn::Peak => result.peak.map(|peak| format!("{:.1}", peak)).unwrap_or_default(),
+            BenchmarkColumn::Failure => result.failure.clone(),
+        }
+    }
+
+    fn width(&self) -> usize {
+        match self {
+            BenchmarkColumn::Site | BenchmarkColumn::Provider => 16,
+            BenchmarkColumn::Host => 24,
+            BenchmarkColumn::Mean | BenchmarkColumn::Peak => 10,
+            BenchmarkColumn::Failure => 24,
+        }
+    }
+
+    fn is_numeric(&self) -> bool {
+        *self == BenchmarkColumn::Mean || *self == BenchmarkColumn::Peak
+    }
+
+    fn compare(&self, a: &BenchmarkResult, b: &BenchmarkResult) -> std::cmp::Ordering {
+        match self {
+            // Fastest first, failures last
+            BenchmarkColumn::Mean => b.mean.unwrap_or(f64::MIN).total_cmp(&a.mean.unwrap_or(f64::MIN)),
+            BenchmarkColumn::Peak => b.peak.unwrap_or(f64::MIN).total_cmp(&a.peak.unwrap_or(f64::MIN)),
+            _ => self.text(a).to_lowercase().cmp(&self.text(b).to_lowercase()),
+        }
+    }
+}
+
+// What the benchmark results dialog is showing
+struct Leaderboard {
+    results: Vec<BenchmarkResult>,
+    sort: (BenchmarkColumn, bool),  // The column and whether it is reversed
+}
+
+impl Default for Leaderboard {
+    fn default() -> Self {
+        Leaderboard { results: Vec::new(), sort: (BenchmarkColumn::Mean, false) }
+    }
+}
+
+impl Leaderboard {
+    fn sorted(&self) -> Vec<BenchmarkResult> {
+        let (column, descending) = self.sort;
+        let mut out = self.results.clone();
+        out.sort_by(|a, b| column.compare(a, b));
+        if descending { out.reverse(); }
+        out
+    }
+
+    fn header(&self, column: BenchmarkColumn) -> String {
+        let descending_opt = if self.sort.0 == column { Some(self.sort.1) } else { None };
+        sort_header(column.title(), descending_opt, column.width(), column.is_numeric())
+    }
+
+    // For the table and for saving
+    fn row(result: &BenchmarkResult) -> String {
+        let cells: Vec<String> = BENCHMARK_COLUMNS.iter().map(|column| fit(&column.text(result), column.width(), column.is_numeric())).collect();
+        cells.join(COLUMN_GAP)
+    }
+
+    fn text(&self) -> String {
+        let headers: Vec<String> = BENCHMARK_COLUMNS.iter().map(|column| fit(column.title(), column.width(), column.is_numeric())).collect();
+        let mut out = format!("Benchmark {}, in Mbits/sec\n\n{}\n", chrono::Local::now().format("%Y-%m-%d %H:%M:%S"), headers.join(COLUMN_GAP));
+        for result in self.sorted() {
+            out += &Leaderboard::row(&result);
+            out += "\n";
+        }
+        out
+    }
+}
+
+fn sort_leaderboard(siv: &mut Cursive, column: BenchmarkColumn) {
+    {
+        let mut leaderboard = LEADERBOARD.lock().unwrap();
+        let descending = leaderboard.sort == (column, false);
+        leaderboard.sort = (column, descending);
+    }
+    refresh_leaderboard(siv);
+}
+
+fn refresh_leaderboard(siv: &mut Cursive) {
+    let results;
+    let headers: Vec<String>;
+    {
+        let leaderboard = LEADERBOARD.lock().unwrap();
+        results = leaderboard.sorted();
+        headers = BENCHMARK_COLUMNS.iter().map(|column| leaderboard.header(*column)).collect();
+    }
+
+    for (index, label) in headers.into_iter().enumerate() {
+        siv.call_on_name(&format!("leaderboard_header_{}", index), |view: &mut Button| view.set_label_raw(label));
+    }
+    siv.call_on_name("leaderboard", |view: &mut SelectView<BenchmarkResult>| {
+        view.clear();
+        for result in results {
+            view.add_item(Leaderboard::row(&result), result);
+        }
+    });
+}
+
+fn leaderboard_dialog(siv: &mut Cursive, results: Vec<BenchmarkResult>) {
+    let failed = results.iter().filter(|result| result.mean.is_none()).count();
+    let count = format!("{} servers tested, {} failed. In Mbits/sec. Press Enter on one to test it for longer", results.len(), failed);
+    let leaderboard = Leaderboard { results, ..Leaderboard::default() };
+
+    let mut header = LinearLayout::horizontal();
+    for (index, column) in BENCHMARK_COLUMNS.iter().enumerate() {
+        let column = *column;
+        if index > 0 { header.add_child(TextView::new(COLUMN_GAP)); }
+        header.add_child(Button::new_raw(leaderboard.header(column), move |s| sort_leaderboard(s, column)).with_name(format!("leaderboard_header_{}", index)));
+    }
+
+    let select = SelectView::<BenchmarkResult>::new()
+        .on_submit(|s, result| {
+            save_args(&result.server.args.with_defaults(&get_config().defaults));
+            save_replay(None);
+            save_state(State::ReloadRequested);
+            log(&format!("leaderboard_dialog: user selected {}", result.server.args.friendly()));
+            s.pop_layer();
+        });
+
+    *LEADERBOARD.lock().unwrap() = leaderboard;
+
+    let layout = LinearLayout::vertical()
+        .child(TextView::new(count))
+        .child(header)
+        .child(select.with_name("leaderboard").scrollable());
+
+    siv.add_layer(Dialog::around(layout)
+        .title("Benchmark results")
+        .button("Save", |s| {
+            let text = LEADERBOARD.lock().unwrap().text();
+            let status = match save_summary(&text) {
+                Ok(filename) => format!("Saved to\n{}", filename),
+                Err(err) => format!("Could not save: {}", err),
+            };
+            s.add_layer(Dialog::info(status));
+        })
+        .button("Close", |s| { s.pop_layer(); })
+    );
+    refresh_leaderboard(siv);
+}
+
+// Several clients at once, to see whether a slowdown is ours or one path's
+fn dashboard_dialog(siv: &mut Cursive) {
+    // The test we're running becomes the first pane - two clients of one server would be busy
+    let args = get_args();
+    if DASHBOARD.lock().unwrap().is_empty() && args.get_server().is_some() {
+        let id = add_pane(&args);
+        save_args(&Args { server: None, ..args });
+        save_replay(None);
+        save_state(State::ReloadRequested);
+        start_pane(id);
+    }
+
+    siv.add_layer(
+        Dialog::around(LinearLayout::vertical().with_name("dashboard_grid"))
+        .title("Dashboard")
+        .button("Add", add_pane_dialog)
+        .button("Start all", |s| {
+            let ids: Vec<usize> = DASHBOARD.lock().unwrap().iter().map(|pane| pane.id).collect();
+            for id in ids { start_pane(id); }
+            refresh_dashboard(s);
+        })
+        .button("Stop all", |_| stop_all_panes())
+        .button("Close", |s| {
+            stop_all_panes();
             s.pop_layer();
-        });
+        })
+        .full_screen()
+    );
+    refresh_dashboard(siv);
+}
 
-    for server in servers {
-        select.add_item(server.friendly(), server);
-    }
+fn pane_view(pane: &Pane, size: (u32, u32)) -> impl View {
+    let id = pane.id;
+    let buttons = LinearLayout::horizontal()
+        .child(Button::new("Start", move |_| start_pane(id)))
+        .child(TextView::new(" "))
+        .child(Button::new("Stop", move |_| stop_pane(id)))
+        .child(TextView::new(" "))
+        .child(Button::new("Remove", move |s| {
+            stop_pane(id);
+            remove_pane(id);
+            refresh_dashboard(s);
+        }));
+    // The controls go first so a graph drawn for a bigger pane can't push them off
+    let layout = LinearLayout::vertical()
+        .child(buttons.child(TextView::new("  ")).child(TextView::new_with_content(pane.status.clone()).no_wrap()))
+        .child(TextView::new_with_content(pane.graph.clone()).no_wrap().full_screen());
+    Panel::new(layout).title(pane.args.friendly()).fixed_size((size.0 as usize, size.1 as usize))
+}
 
-    siv.add_layer(Dialog::around(select.scrollable())
-        .title("Select an iperf3 server")
-        .button("Cancel", |s| { s.pop_layer(); } )
+// Rebuilds the grid of panes, two across
+fn refresh_dashboard(siv: &mut Cursive) {
+    let panes = DASHBOARD.lock().unwrap().clone();
+    let size = pane_size(panes.len());
+    siv.call_on_name("dashboard_grid", |grid: &mut LinearLayout| {
+        grid.clear();
+        if panes.is_empty() {
+            grid.add_child(TextView::new("No clients yet - press Add").full_screen());
+        }
+        for row in panes.chunks(2) {
+            let mut line = LinearLayout::horizontal();
+            for pane in row { line.add_child(pane_view(pane, size)); }
+            grid.add_child(line);
+        }
+    });
+}
+
+fn add_pane_dialog(siv: &mut Cursive) {
+    // Start from the last one, as it's often the same server on another port
+    let last_opt = DASHBOARD.lock().unwrap().last().map(|pane| pane.args.command_line());
+    let layout = LinearLayout::vertical()
+        .child(TextView::new("iperf3 options, eg -c iperf.example.com -p 5201 -R"))
+        .child(EditView::new().content(last_opt.unwrap_or_default()).on_submit(|s, _| add_pane_submit(s)).with_name("pane_options").min_width(60));
+    siv.add_layer(
+        Dialog::around(layout)
+        .title("Add a client")
+        .padding_lrtb(1, 1, 1, 0)
+        .button("OK", add_pane_submit)
+        .button("Cancel", |s| { s.pop_layer(); })
     );
 }
 
-fn enter_server_dialog(siv: &mut Cursive) {
-    log(&format!("enter_server_dialog: start"));
-    let table = LinearLayout::vertical()
-        .child(
-            LinearLayout::horizontal()
-                .child(TextView::new("IPv6:").min_width(20))
-                .child(Checkbox::new().with_name("ipv6"))
-        )
-        .child(
-            LinearLayout::horizontal()
-                .child(TextView::new("Port Range:").min_width(20))
-                .child(EditView::new().with_name("ports").min_width(25))
-        )
-        .child(
-            LinearLayout::horizontal()
-                .child(TextView::new("Reverse:").min_width(20))
-                .child(Checkbox::new().with_name("reverse"))
-        )
-        .child(
-            LinearLayout::horizontal()
-                .child(TextView::new("UDP:").min_width(20))
-                .child(Checkbox::new().with_name("udp"))
-        )
-        .child(
-            LinearLayout::horizontal()
-                .child(TextView::new("Server:").min_width(20))
-                .child(EditView::new().with_name("server").min_width(50))
-        );
+fn add_pane_submit(siv: &mut Cursive) {
+    let options = siv.call_on_name("pane_options", |view: &mut EditView| view.get_content()).unwrap().to_string();
+    let args = match Args::try_parse_from(std::iter::once("iperf3-tui").chain(options.split_whitespace())) {
+        Ok(args) => args.with_defaults(&get_config().defaults),
+        Err(err) => {
+            let err = err.to_string();
+            siv.add_layer(Dialog::info(err.lines().next().unwrap_or("").to_string()));
+            return;
+        }
+    };
+    if args.get_server().is_none() {
+        siv.add_layer(Dialog::info("Give a server with -c"));
+        return;
+    }
+    siv.pop_layer();
+    let id = add_pane(&args);
+    start_pane(id);
+    refresh_dashboard(siv);
+}
+
+fn local_server_dialog(siv: &mut Cursive) {
+    let args = get_args();
+    let port = if args.server_mode { first_port(&args.get_ports()) } else { 5201 };
+    let layout = LinearLayout::vertical()
+        .child(TextView::new("Run iperf3 -s on port"))
+        .child(EditView::new().content(port.to_string()).on_submit(|s, _| local_server_submit(s)).with_name("server_port").min_width(8));
     siv.add_layer(
-        Dialog::new()
-        .title("Enter iperf3 server details")
+        Dialog::around(layout)
+        .title("Run Local Server")
         .padding_lrtb(1, 1, 1, 0)
-        .content(table)
-        .button("OK", |s| {
-            log("pressed OK");
-            let ipv6 = s.call_on_name("ipv6", |view: &mut Checkbox| view.is_checked()).unwrap_or_default();
-            log(&format!("ipv6={ipv6}"));
-            log("here");
-            let ports_arc = s.call_on_name("ports", |view: &mut EditView| view.get_content()).unwrap();
-            let ports_str = ports_arc.to_string();
-            log("got ports");
-            log(&format!("ports_arc={}", ports_arc).to_string());
-            log(&format!("ports_str={}", ports_str).to_string());
-            let reverse = s.call_on_name("reverse", |view: &mut Checkbox| view.is_checked()).unwrap_or_default();
-            let udp = s.call_on_name("udp", |view: &mut Checkbox| view.is_checked()).unwrap_or_default();
-            let server_arc = s.call_on_name("server", |view: &mut EditView| view.get_content()).unwrap();
-            let server_str = server_arc.to_string();
-            log("got values");
-            let mut args = Args::default();
-            if ipv6 { args.ipv6 = ipv6; }
-            if has_content_string(&ports_str) { args.ports = Some(ports_str.to_string()); }
-            if reverse { args.reverse = reverse; }
-            if udp { args.udp = udp; }
-            if has_content_string(&server_str) { args.server = Some(server_str.to_string()); }
-            log(&format!("enter_server_dialog: server={}", server_str).to_string());
-            log(&format!("enter_server_dialog: user entered {}", args.friendly()).to_string());
-            save_args(&args.clone());
-            save_state(State::ReloadRequested);
-            s.pop_layer();
-        })
+        .button("OK", local_server_submit)
         .button("Cancel", |s| { s.pop_layer(); })
     );
 }
 
+fn local_server_submit(siv: &mut Cursive) {
+    let port_str = siv.call_on_name("server_port", |view: &mut EditView| view.get_content()).unwrap().to_string();
+    let port_result = port_str.trim().parse::<u16>();
+    if port_result.is_err() || port_result.clone().unwrap() == 0 {
+        siv.add_layer(Dialog::info("The port should be a number from 1 to 65535"));
+        return;
+    }
+    siv.pop_layer();
+
+    let args = Args { ports: Some(port_result.unwrap().to_string()), server_mode: true, ..Default::default() };
+    log(&format!("local_server_submit: {}", args.friendly()));
+    save_args(&args);
+    save_replay(None);
+    save_state(State::ReloadRequested);
+}
+
 fn about_dialog(siv: &mut Cursive) {
     let info = "iperf3-tui\nby Dave McKellar\nhttps://github.com/dmdmdm\n\nServer List from\nhttps://www.iperf3serverlist.net\nWith thanks!";
 
@@ -796,21 +4369,78 @@ fn about_dialog(siv: &mut Cursive) {
     );
 }
 
+// Favourites then recent tests, and whether to start the last one at launch
+fn recent_menu() -> Tree {
+    let history = load_history();
+    let mut tree = Tree::new();
+    for saved in &history.favourites {
+        let args = saved.args.clone();
+        tree.add_leaf(format!("★ {}", args.friendly()), move |_| start_saved_server(&args));
+    }
+    for saved in history.recent.iter().filter(|saved| !history.is_favourite(&saved.args)) {
+        let args = saved.args.clone();
+        tree.add_leaf(format!("↺ {}", args.friendly()), move |_| start_saved_server(&args));
+    }
+    if !tree.is_empty() { tree.add_delimiter(); }
+    let start_last_txt = if history.start_last { "Start last test at launch: on" } else { "Start last test at launch: off" };
+    tree.add_leaf(start_last_txt, |s| {
+        change_history(|history| history.start_last = !history.start_last);
+        add_menu(s);
+    });
+    tree
+}
+
+// Each server list, to turn it on or off
+fn sources_menu() -> Tree {
+    let mut tree = Tree::new();
+    for source in server_sources() {
+        let location = source.url.clone().or(source.path.clone()).unwrap_or_default();
+        let label = format!("{} {} ({})", if source.enabled { "✓" } else { " " }, source.name, fit(&location, 40, false).trim_end());
+        tree.add_leaf(label, move |s| {
+            change_history(|history| { history.sources_enabled.insert(source.name.clone(), !source.enabled); });
+            add_menu(s);
+        });
+    }
+    tree
+}
+
+// Called again whenever the menu's contents change
 fn add_menu(siv: &mut Cursive) {
-    let download_txt = if servers_file_has_content() { "Refresh list of iperf3 servers"} else { "Download list of iperf3 servers" };
+    let age_opt = servers_age();
+    let download_txt = if !servers_file_has_content() { "Download list of iperf3 servers".to_string() }
+        else if age_opt.is_some() { format!("Refresh list of iperf3 servers ({})", friendly_age(age_opt.unwrap())) }
+        else { "Refresh list of iperf3 servers".to_string() };
+	siv.menubar().clear();
 	siv.menubar()
 	    .add_subtree(
 	        "File",
 	        Tree::new()
-	            .leaf(download_txt, |s| download_servers_dialog(s))
-	            .leaf("Select Server", |s| select_server_dialog(s))
-	            .leaf("Enter Server", |s| enter_server_dialog(s))
-	            .leaf("About", |s| about_dialog(s))
+	            .leaf(download_txt, download_servers_dialog)
+	            .subtree("Server Lists", sources_menu())
+	            .leaf("Select Server", select_server_dialog)
+	            .leaf("Enter Server", enter_server_dialog)
+	            .subtree("Recent", recent_menu())
+	            .leaf("Benchmark Servers", benchmark_dialog)
+	            .leaf("Dashboard", dashboard_dialog)
+	            .leaf("Run Local Server", local_server_dialog)
+	            .leaf("Record on/off", record_dialog)
+	            .leaf("Open Recording", open_recording_dialog)
+	            .leaf("About", about_dialog)
 	            .leaf("Quit", on_quit)
+	    )
+	    .add_subtree(
+	        "View",
+	        Tree::new()
+	            .leaf("SUM / each stream (s)", |_| toggle_show_streams())
+	    )
+	    .add_subtree(
+	        "Replay",
+	        Tree::new()
+	            .leaf("Pause / resume (space)", |_| replay_pause())
+	            .leaf("Step (n)", |_| replay_step())
+	            .leaf("Faster (+)", |_| replay_faster())
+	            .leaf("Slower (-)", |_| replay_slower())
 	    );
-	
-    siv.set_autohide_menu(false);
-    siv.add_global_callback(Key::Esc, |s| s.select_menubar());
 }
 
 fn main() {
@@ -819,9 +4449,31 @@ fn main() {
         process::exit(1);
     }
 
-    let args = Args::parse();
+    let cli_args = Args::parse();
+    let config = match load_config(cli_args.config.clone()) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("Could not read the config file {}", err);
+            process::exit(1);
+        }
+    };
+    let mut args = cli_args.with_defaults(&config.defaults);
+    save_config(config);
+
+    // Not when the command line asks for a test of its own, eg -P 4
+    if args.get_server().is_none() && cli_args.same_test(&Args::default()) && args.replay.is_none() && !args.server_mode
+        && let Some(last) = load_history().last_to_start() {
+        // Keep our own options from the command line
+        args = Args { text: args.text, record: args.record, headless: args.headless, sparkline: args.sparkline, ..last };
+    }
     save_args(&args);
     save_state(State::Normal);
+    save_recording(args.record.clone());
+    save_replay(args.replay.clone());
+
+    if args.headless {
+        process::exit(run_headless(&args));
+    }
 
     let mut siv = cursive::default();
     let sink = siv.cb_sink().clone();
@@ -843,10 +4495,19 @@ fn main() {
     );
 
     add_menu(&mut siv);
+    siv.set_autohide_menu(false);
+    siv.add_global_callback(Key::Esc, |s| s.select_menubar());
 
     siv.add_global_callback('q', on_quit);
+    siv.add_global_callback('s', |_| toggle_show_streams());
+    siv.add_global_callback(' ', |_| replay_pause());
+    siv.add_global_callback('n', |_| replay_step());
+    siv.add_global_callback('+', |_| replay_faster());
+    siv.add_global_callback('-', |_| replay_slower());
 
+    let refresh_sink = sink.clone();
     std::thread::spawn(move || { background_graph(&sink, &content_graph) });
+    std::thread::spawn(move || { background_refresh_servers(&refresh_sink) });
 
     siv.set_fps(1);
 
@@ -860,6 +4521,7 @@ fn main() {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use cursive::theme::ColorType;
 
     #[test]
     fn it_works() {
@@ -871,4 +4533,630 @@ mod tests {
         let padded = left_pad("hello".to_string(), 9);
         assert_eq!(padded, "    hello");
     }
+
+    #[test]
+    fn json_stream_events() {
+        let line = r#"{"event":"interval","data":{"streams":[{"socket":5,"start":0,"end":1.0,"seconds":1.0,"bytes":117833728,"bits_per_second":942669824.0,"retransmits":3,"snd_cwnd":385024,"omitted":false,"sender":true}],"sum":{"start":0,"end":1.0,"seconds":1.0,"bytes":117833728,"bits_per_second":942669824.0,"retransmits":3,"omitted":false,"sender":true}}}"#;
+        let event = parse_json_line(line);
+        assert!(matches!(event, Some(JsonEvent::Interval(_))));
+        if let Some(JsonEvent::Interval(interval)) = event {
+            let sample = Sample::from_json("SUM", "", &interval.sum);
+            assert_eq!(sample.bytes, 117833728);
+            assert_eq!(sample.retransmits, Some(3));
+            assert!((sample.mbits_per_second() - 942.669824).abs() < 0.000001);
+        }
+
+        let line = r#"{"event":"error","data":"unable to connect to server - server may have stopped running or use a different port, firewall issue, etc.: Connection refused"}"#;
+        assert!(matches!(parse_json_line(line), Some(JsonEvent::Error(_))));
+
+        assert!(parse_json_line("Connecting to host localhost, port 5201").is_none());
+    }
+
+    #[test]
+    fn text_lines() {
+        let sample = parse_text_line("[  5]   0.00-1.00   sec   112 MBytes   941 Mbits/sec    0    376 KBytes").unwrap();
+        assert_eq!(sample.stream, "5");
+        assert_eq!(sample.bits_per_second, 941e6);
+
+        let sample = parse_text_line("[SUM]   1.00-2.00   sec  1.09 GBytes  9.38 Gbits/sec    0").unwrap();
+        assert_eq!(sample.stream, "SUM");
+        assert_eq!(sample.mbits_per_second(), 9380.0);
+
+        assert!(parse_text_line("[ ID] Interval           Transfer     Bitrate         Retr  Cwnd").is_none());
+        assert!(parse_text_line("- - - - - - - - - - - - - - - - - - - - - - - - -").is_none());
+
+        let sample = parse_text_line("[  5]   0.00-1.00   sec   112 MBytes   941 Mbits/sec   14   1.50 MBytes").unwrap();
+        assert_eq!(sample.retransmits, Some(14));
+        assert_eq!(sample.snd_cwnd, Some(1572864));
+
+        let sample = parse_text_line("[  5]   0.00-1.00   sec   128 KBytes  1.05 Mbits/sec  0.019 ms  3/91 (3.3%)").unwrap();
+        assert_eq!(sample.jitter_ms, Some(0.019));
+        assert_eq!(sample.lost_percent, Some(3.3));
+    }
+
+    #[test]
+    fn parallel_streams() {
+        let line = r#"{"event":"interval","data":{"streams":[{"socket":5,"bits_per_second":4e8},{"socket":7,"bits_per_second":6e8}],"sum":{"bits_per_second":1e9}}}"#;
+        let event = parse_json_line(line);
+        if let Some(JsonEvent::Interval(interval)) = event {
+            let samples = interval.samples();
+            let names: Vec<&str> = samples.iter().map(|sample| sample.stream.as_str()).collect();
+            assert_eq!(names, vec!["SUM", "5", "7"]);
+        }
+        else {
+            panic!("Expected an interval");
+        }
+
+        let mut units = "Mbits".to_string();
+        let scaled = scale_many(&mut units, &vec![vec![1500.0, 2500.0], vec![500.0]]);
+        assert_eq!(units, "Gbits");
+        assert_eq!(scaled, vec![vec![1.5, 2.5], vec![0.5]]);
+
+        let args = Args::parse_from(["iperf3-tui", "-P", "4", "-c", "example.com"]);
+        assert_eq!(args.get_parallel(), 4);
+        assert!(Args::try_parse_from(["iperf3-tui", "-P", "0"]).is_err());
+    }
+
+    #[test]
+    fn bidir() {
+        let line = r#"{"event":"interval","data":{"streams":[{"socket":5,"bits_per_second":5e7,"sender":true},{"socket":7,"bits_per_second":3e8,"sender":false}],"sum":{"bits_per_second":5e7,"sender":true},"sum_bidir_reverse":{"bits_per_second":3e8,"sender":false}}}"#;
+        if let Some(JsonEvent::Interval(interval)) = parse_json_line(line) {
+            let names: Vec<String> = interval.samples().iter().map(|sample| sample.name()).collect();
+            assert_eq!(names, vec!["SUM TX", "SUM RX"]);
+        }
+        else {
+            panic!("Expected an interval");
+        }
+
+        let sample = parse_text_line("[  5][TX-C]   0.00-1.00   sec  5.88 MBytes  49.3 Mbits/sec    0   81.2 KBytes").unwrap();
+        assert_eq!(sample.name(), "5 TX");
+        assert_eq!(sample.retransmits, Some(0));
+        let sample = parse_text_line("[SUM][RX-C]   0.00-1.00   sec  35.9 MBytes   301 Mbits/sec").unwrap();
+        assert_eq!(sample.name(), "SUM RX");
+
+        assert!(Args::try_parse_from(["iperf3-tui", "-R", "--bidir"]).is_err());
+    }
+
+    #[test]
+    fn recording() {
+        let mut args = Args { server: Some("example.com".to_string()), bidir: true, ..Default::default() };
+        let mut sample = Sample {
+            stream: "SUM".to_string(),
+            direction: "RX".to_string(),
+            bits_per_second: 3e8,
+            jitter_ms: Some(0.25),
+            ..Default::default()
+        };
+        let record = Record::new(&args, &sample);
+        assert_eq!(record.direction, "download");
+        assert_eq!(record.protocol, "tcp");
+        sample.retransmits = Some(2);
+        args.udp = true;
+        assert_eq!(Record::new(&args, &sample).retransmits, None);
+        args.udp = false;
+
+        let dir = std::env::temp_dir().join(format!("iperf3-tui-test-{}", process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for basename in ["session.csv", "session.jsonl"] {
+            let filename = dir.join(basename).to_str().unwrap().to_string();
+            let _ = std::fs::remove_file(&filename);
+            append_record(&filename, &record).unwrap();
+            append_record(&filename, &record).unwrap();
+            let contents = std::fs::read_to_string(&filename).unwrap();
+            let header_count = if is_csv_filename(&filename) { 1 } else { 0 };
+            assert_eq!(contents.lines().count(), 2 + header_count);
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn replay() {
+        let record = Record {
+            timestamp: "2025-08-08T13:10:00.000+01:00".to_string(),
+            server: "example.com".to_string(),
+            protocol: "tcp".to_string(),
+            direction: "upload".to_string(),
+            stream: "SUM".to_string(),
+            ..Default::default()
+        };
+        let mut records = vec![record.clone(), record.clone()];
+        records[1].timestamp = "2025-08-08T13:10:01.500+01:00".to_string();
+        records[1].direction = "download".to_string();
+        for stream in ["5", "7", "9", "11"] {
+            records.push(Record { stream: stream.to_string(), ..record.clone() });
+        }
+
+        let args = args_from_records(&records);
+        assert_eq!(args.get_server_as_string(), "example.com");
+        assert!(args.bidir);
+        assert_eq!(args.get_parallel(), 2);
+        assert_eq!(records[1].to_sample(&args).direction, "RX");
+        assert_eq!(record_gap(&records[0], &records[1]), Duration::from_millis(1500));
+
+        // A recording that went on to test another server can't be replayed as one
+        let filename = std::env::temp_dir().join(format!("iperf3-tui-test-{}-replay.jsonl", process::id())).to_str().unwrap().to_string();
+        let _ = std::fs::remove_file(&filename);
+        append_record(&filename, &record).unwrap();
+        append_record(&filename, &record).unwrap();
+        assert_eq!(read_recording(&filename).unwrap().len(), 2);
+        append_record(&filename, &Record { server: "other.example.com".to_string(), ..record.clone() }).unwrap();
+        assert!(read_recording(&filename).unwrap_err().to_string().contains("other.example.com"));
+        let _ = std::fs::remove_file(&filename);
+    }
+
+    #[test]
+    fn headless() {
+        assert_eq!(friendly_bitrate(9380.0), "9.38 Gbits/sec");
+        assert_eq!(friendly_bitrate(0.0005), "0.50 Kbits/sec");
+
+        let args = Args::parse_from(["iperf3-tui", "--headless", "-c", "example.com"]);
+        let sample = Sample { bits_per_second: 941.23e6, retransmits: Some(2), ..Default::default() };
+        let line = headless_line(&args, &sample);
+        assert!(line.ends_with("upload    941.23 Mbits/sec  retr 2"), "{}", line);
+
+        let mut stats = Stats::default();
+        stats.add(900.0);
+        stats.add(1000.0);
+        assert!(headless_summary("Upload", &stats).contains("average 950.00 Mbits/sec"));
+        assert!(Args::try_parse_from(["iperf3-tui", "--sparkline"]).is_err());
+    }
+
+    #[test]
+    fn stats() {
+        let mut stats = Stats::default();
+        for value in [5.0, 1.0, 4.0, 2.0, 3.0] {
+            stats.add(value);
+        }
+        assert_eq!(stats.current(), 3.0);
+        assert_eq!(stats.min(), 1.0);
+        assert_eq!(stats.max(), 5.0);
+        assert_eq!(stats.mean(), 3.0);
+        assert_eq!(stats.median(), 3.0);
+        assert!((stats.percentile(95.0) - 4.8).abs() < 1e-9);
+        assert!((stats.stddev() - 2.0_f64.sqrt()).abs() < 1e-9);
+
+        let mut stats = Stats::default();
+        stats.add(2000.0);
+        stats.add(4000.0);
+        assert_eq!(stats.friendly(), "Now 4.00  Min 2.00  Max 4.00  Mean 3.00  Median 3.00  P95 3.90  StdDev 1.00 Gbits/sec");
+    }
+
+    #[test]
+    fn fixed_duration() {
+        let (role, sample) = parse_text_summary_line("[  5]   0.00-10.00  sec  1.09 GBytes   939 Mbits/sec    0             sender").unwrap();
+        assert_eq!(role, "sender");
+        assert_eq!(sample.bits_per_second, 939e6);
+        assert_eq!(sample.bytes, 1170378588);
+        let (role, _) = parse_text_summary_line("[SUM][RX-C]   0.00-10.04  sec  1.09 GBytes   934 Mbits/sec                  receiver").unwrap();
+        assert_eq!(role, "receiver");
+        assert!(parse_text_summary_line("iperf Done.").is_none());
+
+        let line = r#"{"event":"end","data":{"sum_sent":{"bytes":1170378588,"bits_per_second":939e6,"retransmits":0,"sender":true},"sum_received":{"bytes":1170000000,"bits_per_second":934e6,"sender":true}}}"#;
+        if let Some(JsonEvent::End(end)) = parse_json_line(line) {
+            let summary = end.summary();
+            assert_eq!(summary.len(), 2);
+            assert_eq!(summary[0].friendly(), "Sender              1.1 GB  939.00 Mbits/sec  retr 0");
+            assert_eq!(summary[1].label, "Receiver");
+        }
+        else {
+            panic!("Expected the end");
+        }
+
+        assert_eq!(progress_bar(3, 10, 40), "3/10 s [███░░░░░░░] 7 s left");
+        assert_eq!(Args::parse_from(["iperf3-tui", "-t", "30"]).get_time(), 30);
+    }
+
+    #[test]
+    fn latency_probe() {
+        assert_eq!(first_port("5200-5209"), 5200);
+        assert_eq!(first_port(""), 5201);
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert!(probe_latency("127.0.0.1", port, false, Duration::from_secs(2)).is_some());
+        assert!(probe_latency("127.0.0.1", port, true, Duration::from_secs(2)).is_some());   // Only IPv4 resolves
+        drop(listener);
+        assert!(probe_latency("127.0.0.1", port, false, Duration::from_secs(2)).is_none());
+
+        assert_eq!(friendly_latency(None), "...");
+        assert_eq!(friendly_latency(Some(None)), "fail");
+        assert_eq!(friendly_latency(Some(Some(Duration::from_millis(12)))), "12 ms");
+    }
+
+    #[test]
+    fn server_filter() {
+        let mut server = ParsedServer {
+            continent: "Europe".to_string(),
+            country: "DE".to_string(),
+            site: "Frankfurt".to_string(),
+            provider: "Example Transit".to_string(),
+            ..Default::default()
+        };
+
+        let mut picker = ServerPicker::default();
+        assert!(picker.matches(&server));
+        picker.filter = "frank transit".to_string();
+        assert!(picker.matches(&server));
+        picker.filter = "frankfurt paris".to_string();
+        assert!(!picker.matches(&server));
+
+        picker.filter = String::new();
+        picker.continent = Some("Asia".to_string());
+        assert!(!picker.matches(&server));
+        picker.continent = Some("Europe".to_string());
+        picker.ipv6_only = true;
+        assert!(!picker.matches(&server));
+        server.ipv6_capable = true;
+        assert!(picker.matches(&server));
+    }
+
+    #[test]
+    fn server_table() {
+        assert_eq!(speed_value("10"), Some(10.0));
+        assert_eq!(speed_value("2x100"), Some(2.0));
+        assert_eq!(speed_value(""), None);
+        assert_eq!(fit("Frankfurt", 6, false), "Frank…");
+        assert_eq!(fit("10", 6, true), "    10");
+
+        let mut picker = ServerPicker::default();
+        for (site, speed) in [("Paris", "10"), ("Berlin", "100"), ("Tokyo", ""), ("Austin", "40")] {
+            picker.servers.push(ParsedServer { site: site.to_string(), speed: speed.to_string(), ..Default::default() });
+        }
+        let sites = |picker: &ServerPicker| picker.visible().iter().map(|server| server.site.clone()).collect::<Vec<String>>();
+        assert_eq!(sites(&picker), vec!["Paris", "Berlin", "Tokyo", "Austin"]);
+        picker.sort = Some((Column::Speed, false));
+        assert_eq!(sites(&picker), vec!["Berlin", "Austin", "Paris", "Tokyo"]);
+        picker.sort = Some((Column::Site, true));
+        assert_eq!(sites(&picker), vec!["Tokyo", "Paris", "Berlin", "Austin"]);
+        assert_eq!(Column::Site.header(picker.sort, 8), "Site ▼  ");
+    }
+
+    #[test]
+    fn tcp_strip_lines() {
+        let strip = tcp_strip(&[0.0, 1.0, 40.0, 1.0], &[385024.0]);
+        assert_eq!(strip.source(), "  Retr        1     ▁▁█▁\n  Cwnd   376 KB     █");
+        let red: Vec<&str> = strip.spans().filter(|span| span.attr.color.front != ColorType::InheritParent).map(|span| span.content).collect();
+        assert_eq!(red, vec!["█"]);
+    }
+
+    #[test]
+    fn udp_strip_lines() {
+        assert_eq!(sparkline(&[0.0, 1.0, 2.0]), "▁▅█");
+        let strip = udp_strip(&[0.5, 2.0], &[0.0, 1.5]);
+        assert!(strip.contains("2.000 ms"));
+        assert!(strip.contains("1.50 %"));
+    }
+
+    #[test]
+    fn favourites_and_recent() {
+        let mut history = History::default();
+        for server in ["a.example.com", "b.example.com", "a.example.com"] {
+            history.add_recent(&Args::parse_from(["iperf3-tui", "-c", server]));
+        }
+        let hosts: Vec<String> = history.recent.iter().map(|saved| saved.args.get_server_as_string()).collect();
+        assert_eq!(hosts, vec!["a.example.com", "b.example.com"]);
+        for index in 0..20 {
+            history.add_recent(&Args::parse_from(["iperf3-tui", "-c", &format!("{}.example.com", index)]));
+        }
+        assert_eq!(history.recent.len(), History::MAX_RECENT);
+        assert!(history.last_to_start().is_none());
+        history.start_last = true;
+        assert_eq!(history.last_to_start().unwrap().get_server_as_string(), "19.example.com");
+
+        let paris = Args::parse_from(["iperf3-tui", "-c", "paris.example.com", "-p", "5201-5209", "-R"]);
+        history.toggle_favourite(&paris);
+        assert!(history.is_favourite(&paris));
+        let listed = ParsedServer {
+            args: Args::parse_from(["iperf3-tui", "-c", "paris.example.com", "-p", "5201-5209"]),
+            country: "FR".to_string(),
+            ..Default::default()
+        };
+        let pinned = history.pinned_servers(&[listed]);
+        assert_eq!(pinned.len(), 1 + History::MAX_RECENT);
+        assert_eq!((pinned[0].marker.as_str(), pinned[0].country.as_str(), pinned[0].args.reverse), ("★", "FR", true));
+        assert_eq!(pinned[1].marker, "↺");
+        history.toggle_favourite(&paris);
+        assert!(!history.is_favourite(&paris));
+
+        // Our own options aren't saved
+        let args = Args::parse_from(["iperf3-tui", "-c", "example.com", "--record", "x.csv", "--headless"]);
+        let json = serde_json::to_string(&args).unwrap();
+        assert!(!json.contains("x.csv"));
+        let parsed: Args = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, Args::parse_from(["iperf3-tui", "-c", "example.com"]));
+
+        // So a saved test is the same test whatever else was on the command line
+        let mut history = History::default();
+        history.add_recent(&parsed);
+        history.toggle_favourite(&parsed);
+        history.add_recent(&args);
+        assert_eq!(history.recent.len(), 1);
+        assert!(history.is_favourite(&args));
+
+        // The last test only starts at launch when the command line has no test of its own
+        assert!(Args::parse_from(["iperf3-tui", "--record", "x.csv"]).same_test(&Args::default()));
+        assert!(!Args::parse_from(["iperf3-tui", "-P", "4"]).same_test(&Args::default()));
+    }
+
+    #[test]
+    fn user_config() {
+        let config = parse_config("").unwrap();
+        assert_eq!(config.interval, 1.0);
+        assert_eq!(config.timeout, 5);
+
+        let config = parse_config("interval = 0.5\ntimeout = 10\n[defaults]\nparallel = 4\nbidir = true\nports = \"5201-5209\"\n").unwrap();
+        assert_eq!(config.interval, 0.5);
+        assert_eq!(config.timeout, 10);
+
+        // The command line wins
+        let args = Args::parse_from(["iperf3-tui", "-P", "2", "-R", "-c", "example.com"]).with_defaults(&config.defaults);
+        assert_eq!((args.get_parallel(), args.get_ports(), args.reverse, args.bidir), (2, "5201-5209".to_string(), true, false));
+        let args = Args::parse_from(["iperf3-tui", "-c", "example.com"]).with_defaults(&config.defaults);
+        assert_eq!((args.get_parallel(), args.bidir), (4, true));
+
+        // And can turn off what the config turns on
+        let config = parse_config("[defaults]\nudp = true\nreverse = true\nreconnect = 3\n").unwrap();
+        let args = Args::parse_from(["iperf3-tui", "--no-udp", "--no-reverse", "-c", "example.com"]).with_defaults(&config.defaults);
+        assert_eq!((args.udp, args.reverse, args.reconnect), (false, false, Some(3)));
+        assert!(Args::try_parse_from(["iperf3-tui", "-u", "--no-udp"]).is_err());
+
+        assert!(parse_config("interval = 0").is_err());
+        assert!(parse_config("intervall = 1").is_err());
+        assert!(parse_config("[defaults]\nreverse = true\nbidir = true").is_err());
+        assert!(parse_config("[defaults]\nparallel = 500").is_err());
+        assert!(load_config(Some("/nonexistent/config.toml".to_string())).is_err());
+    }
+
+    #[test]
+    fn busy_ports() {
+        assert_eq!(port_range("5200-5203"), Some(vec![5200, 5201, 5202, 5203]));
+        assert_eq!(port_range("5201"), Some(vec![5201]));
+        assert_eq!(port_range(" 5201 - 5202 "), Some(vec![5201, 5202]));
+        assert_eq!(port_range(""), None);
+        assert_eq!(port_range("5209-5200"), None);
+        assert_eq!(port_range("1-65535"), None);
+        assert!(is_busy("iperf3: error - the server is busy running a test. try again later"));
+        assert!(!is_busy("unable to connect to server: Connection refused"));
+        assert!(is_busy("Every port of example.com (5201-5209) is busy running a test, try again later"));
+    }
+
+    #[test]
+    fn reconnect_gaps() {
+        let args = Args::parse_from(["iperf3-tui", "--reconnect", "3", "-c", "example.com"]);
+        assert_eq!(args.reconnect, Some(3));
+
+        let mut graph = Graph::default();
+        let sample = Sample { stream: "SUM".to_string(), bits_per_second: 1e9, ..Default::default() };
+        graph.add(&args, &sample);
+        graph.add_gap(Duration::from_secs(3));
+        graph.add(&args, &sample);
+        assert_eq!(graph.bitrates.len(), 5);
+        assert!(graph.bitrates[1..4].iter().all(|bitrate| bitrate.is_nan()));
+        assert_eq!(graph.stats.values.len(), 2);
+        assert_eq!(average(&graph.bitrates), 1000.0);
+        assert!(graph.draw(&args).source().contains("Reconnected 1 time, 3 s not measured"));
+    }
+
+    #[test]
+    fn benchmark_leaderboard() {
+        let mut leaderboard = Leaderboard::default();
+        for (site, bitrates, failure) in [("Paris", vec![400.0, 600.0], ""), ("Tokyo", vec![], "Connection refused\nmore"), ("Berlin", vec![900.0], "")] {
+            let server = ParsedServer { site: site.to_string(), ..Default::default() };
+            let mut stats = Stats::default();
+            for bitrate in bitrates { stats.add(bitrate); }
+            leaderboard.results.push(BenchmarkResult::new(&server, &stats, failure));
+        }
+        assert_eq!(leaderboard.results[0].mean, Some(500.0));
+        assert_eq!(leaderboard.results[0].peak, Some(600.0));
+        assert_eq!(leaderboard.results[1].failure, "Connection refused");
+
+        let sites = |leaderboard: &Leaderboard| leaderboard.sorted().iter().map(|result| result.server.site.clone()).collect::<Vec<String>>();
+        assert_eq!(sites(&leaderboard), vec!["Berlin", "Paris", "Tokyo"]);
+        leaderboard.sort = (BenchmarkColumn::Site, false);
+        assert_eq!(sites(&leaderboard), vec!["Berlin", "Paris", "Tokyo"]);
+        leaderboard.sort = (BenchmarkColumn::Site, true);
+        assert_eq!(sites(&leaderboard), vec!["Tokyo", "Paris", "Berlin"]);
+        assert!(leaderboard.text().contains("Connection refused"));
+    }
+
+    #[test]
+    fn dashboard_panes() {
+        let args = Args::parse_from(["iperf3-tui", "-c", "example.com", "-p", "5201-5209", "-P", "2", "-R", "--reconnect", "3"]);
+        let line = args.command_line();
+        assert_eq!(line, "-p 5201-5209 -P 2 -R --reconnect 3 -c example.com");
+        assert_eq!(Args::parse_from(std::iter::once("iperf3-tui").chain(line.split_whitespace())), args);
+
+        let first = add_pane(&args);
+        let second = add_pane(&Args::parse_from(["iperf3-tui", "-c", "other.example.com"]));
+        assert_ne!(first, second);
+        assert!(pane_should_stop(first, 0));   // Not started
+        change_pane(first, |pane| pane.running = true);
+        assert!(!pane_should_stop(first, 0));
+        assert!(pane_should_stop(first, 1));   // Restarted since
+        stop_all_panes();
+        assert!(pane_should_stop(first, 0));
+        remove_pane(first);
+        assert!(get_pane(first).is_none());
+        assert_eq!(get_pane(second).unwrap().args.get_server_as_string(), "other.example.com");
+        remove_pane(second);
+
+        let (one_width, one_height) = pane_size(1);
+        let (two_width, two_height) = pane_size(3);
+        assert!(two_width < one_width && two_height < one_height);
+    }
+
+    #[test]
+    fn local_server_lines() {
+        assert_eq!(parse_accepted_line("Accepted connection from 192.168.1.10, port 50412"), Some("192.168.1.10".to_string()));
+        assert_eq!(parse_accepted_line("[  5] local 192.168.1.2 port 5201 connected to 192.168.1.10 port 50414"), None);
+
+        let sample = parse_text_line("[  5][RX-S]   1.00-2.00   sec   112 MBytes   941 Mbits/sec").unwrap();
+        assert_eq!(sample.direction, "RX");
+        assert_eq!(sample.mbits_per_second(), 941.0);
+
+        let args = Args::parse_from(["iperf3-tui", "--server-mode", "-p", "5300"]);
+        assert_eq!(args.friendly(), "Local server on port 5300");
+        assert!(Args::try_parse_from(["iperf3-tui", "--server-mode", "-c", "example.com"]).is_err());
+    }
+
+    #[test]
+    fn client_options() {
+        let args = Args::parse_from(["iperf3-tui", "-c", "example.com", "-b", "100M/10", "-w", "256K", "-M", "1400", "-l", "128K", "-O", "2", "-Z", "-C", "bbr", "-S", "0x10", "-B", "192.168.1.2", "-A", "2,3"]);
+        assert_eq!(args.tos, Some(16));
+        assert_eq!((parse_tos_arg("020"), parse_tos_arg("16"), parse_tos_arg("0")), (Ok(16), Ok(16), Ok(0)));
+        let line = args.command_line();
+        assert_eq!(line, "-A 2,3 -b 100M/10 -B 192.168.1.2 -C bbr -l 128K -M 1400 -O 2 -S 16 -w 256K -Z -c example.com");
+        assert_eq!(Args::parse_from(std::iter::once("iperf3-tui").chain(line.split_whitespace())), args);
+        assert_eq!(args.friendly(), "example.com CPU 2,3 bitrate 100M/10 from 192.168.1.2 congestion bbr length 128K MSS 1400 omit 2 s TOS 0x10 window 256K zero-copy");
+
+        for bad in [["-b", "fast"], ["-w", "256KB"], ["-M", "0"], ["-M", "10000"], ["-O", "601"], ["-C", "b b r"], ["-S", "256"], ["-S", "08"], ["-A", "2,3,4"]] {
+            assert!(Args::try_parse_from(["iperf3-tui", "-c", "example.com", bad[0], bad[1]]).is_err(), "{:?}", bad);
+        }
+        assert!(parse_config("[defaults]\nwindow = \"big\"\n").unwrap_err().contains("Size should be"));
+        assert_eq!(parse_config("[defaults]\nbitrate = \"1G\"\n").unwrap().defaults.bitrate, Some("1G".to_string()));
+
+        assert!(parse_text_line("[  5]   0.00-1.00   sec   112 MBytes   941 Mbits/sec    0    376 KBytes       (omitted)").is_none());
+    }
+
+    #[test]
+    fn server_list_columns() {
+        let args = parse_server_command("iperf3 -c iperf.example.com", "-p 5200-5209,-6,--omit 3,IPv4/IPv6").unwrap();
+        assert_eq!(args.get_server_as_string(), "iperf.example.com");
+        assert_eq!(args.get_ports(), "5200-5209");
+        assert!(args.ipv6);
+
+        // Flags clap doesn't know, attached values and a bare host
+        let args = parse_server_command("iperf3 --client=speed.example.net --no-delay", "-p5002 --cport 6000").unwrap();
+        assert_eq!(args.command_line(), "-p 5002 -c speed.example.net");
+        assert_eq!(parse_server_command("iperf3 192.0.2.7", "").unwrap().get_server_as_string(), "192.0.2.7");
+
+        assert!(parse_server_command("iperf3 -s", "").is_err());
+        assert!(parse_server_command("iperf3 -c example.com", "-p 5201-9999").is_err());
+        assert!(parse_server_command("iperf3 -c", "").is_err());
+        assert!(parse_server_command("iperf3 -B 192.0.2.1", "").is_err());
+        assert_eq!(parse_server_command("iperf3 -B 192.0.2.1 198.51.100.9", "").unwrap().get_server_as_string(), "198.51.100.9");
+        assert_eq!(parse_server_command("iperf3 --no-delay host.example.net", "").unwrap().get_server_as_string(), "host.example.net");
+        assert_eq!(parse_server_command("iperf3 -V 192.0.2.7", "-R").unwrap().get_server_as_string(), "192.0.2.7");
+
+        let filename = std::env::temp_dir().join(format!("iperf3-tui-test-{}.csv", process::id())).to_str().unwrap().to_string();
+        std::fs::write(&filename, "IP/HOST,OPTIONS,GB/S,COUNTRY,SITE,PROVIDER,CONTINENT,STATUS\n\
+            iperf3 -c one.example.com,-p 5201,10,DE,Frankfurt,Example,Europe,OK\n\
+            iperf3 -s,-p 5201,10,DE,Berlin,Example,Europe,OK\n\
+            iperf3 -c two.example.com,--weird-flag,1,FR,Paris,Other,Europe,OK\n").unwrap();
+        let mut warnings = Vec::new();
+        let servers = parse_servers_file(&filename, &mut warnings).unwrap();
+        let _ = std::fs::remove_file(&filename);
+        assert_eq!(servers.len(), 2);
+        assert_eq!(warnings, vec!["Line 3: no server address".to_string()]);
+        assert!(skipped_rows_text(&warnings).starts_with("\nSkipped 1 row:"));
+    }
+
+    #[test]
+    fn conditional_refresh() {
+        use std::io::BufRead;
+        use std::net::TcpListener;
+
+        // Serves the list with an ETag, says it's unchanged when asked with that ETag, then sends junk
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/servers.csv", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            let list = "IP/HOST,OPTIONS,GB/S,COUNTRY,SITE,PROVIDER,CONTINENT,STATUS\niperf3 -c one.example.com,-p 5201,10,DE,Frankfurt,Example,Europe,OK\n";
+            for response in [format!("200 OK\r\nETag: \"v1\"\r\nContent-Length: {}\r\n\r\n{}", list.len(), list),
+                             "304 Not Modified\r\n\r\n".to_string(),
+                             "200 OK\r\nETag: \"v2\"\r\nContent-Length: 5\r\n\r\njunk\n".to_string()] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = String::new();
+                let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).unwrap() == 0 || line == "\r\n" { break; }
+                    request += &line;
+                }
+                requests.push(request);
+                stream.write_all(format!("HTTP/1.1 {}", response).as_bytes()).unwrap();
+            }
+            requests
+        });
+
+        let dir = std::env::temp_dir().join(format!("iperf3-tui-test-refresh-{}", process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let filename = dir.join("servers.csv").to_str().unwrap().to_string();
+        let meta_filename = dir.join("servers.json").to_str().unwrap().to_string();
+
+        assert_eq!(refresh_servers_file(&url, &filename, &meta_filename, false), "Downloaded 1 servers");
+        let meta = load_servers_meta(&meta_filename);
+        assert_eq!(meta.etag, Some("\"v1\"".to_string()));
+        assert!(meta.age().unwrap() < chrono::TimeDelta::minutes(1));
+        assert_eq!(refresh_servers_file(&url, &filename, &meta_filename, false), "The list of 1 servers is up to date");
+        assert!(refresh_servers_file(&url, &filename, &meta_filename, false).ends_with("so kept the one we had"));
+        assert_eq!(parse_servers_file(&filename, &mut Vec::new()).unwrap().len(), 1);
+        assert_eq!(load_servers_meta(&meta_filename).etag, Some("\"v1\"".to_string()));
+
+        let requests = server.join().unwrap();
+        assert!(!requests[0].to_lowercase().contains("if-none-match"));
+        assert!(requests[1].to_lowercase().contains("if-none-match: \"v1\""));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert_eq!(friendly_age(chrono::TimeDelta::minutes(5)), "5 minutes old");
+        assert_eq!(friendly_age(chrono::TimeDelta::hours(30)), "30 hours old");
+        assert_eq!(friendly_age(chrono::TimeDelta::days(3)), "3 days old");
+    }
+
+    #[test]
+    fn server_sources_config() {
+        let config = parse_config("[[sources]]\nname = \"lab\"\npath = \"~/lab-servers.json\"\n\n[[sources]]\nname = \"team\"\nurl = \"https://intranet.example.com/iperf3.csv?v=2\"\nenabled = false\n").unwrap();
+        assert_eq!(config.sources.len(), 2);
+        assert!(config.sources[0].enabled && config.sources[0].is_json());
+        assert!(!config.sources[1].enabled && !config.sources[1].is_json());
+        assert!(!expand_home("~/lab-servers.json").starts_with('~'));
+
+        assert!(parse_config("[[sources]]\nname = \"lab\"\n").unwrap_err().contains("needs either a url or a path"));
+        assert!(parse_config("[[sources]]\nname = \"public\"\npath = \"a.csv\"\n").unwrap_err().contains("more than one source called public"));
+        assert!(parse_config("[[sources]]\nurl = \"https://example.com/a.csv\"\n").unwrap_err().contains("needs a name"));
+
+        let filename = std::env::temp_dir().join(format!("iperf3-tui-test-{}.json", process::id())).to_str().unwrap().to_string();
+        std::fs::write(&filename, r#"[{"host": "iperf.lab.example.com", "port": "5201-5209", "site": "Lab 2"},
+                                      {"cmd": "iperf3 -c 10.1.2.3 -6", "site": "Rack 4"},
+                                      {"site": "No address"}]"#).unwrap();
+        let mut warnings = Vec::new();
+        let servers = parse_source_file(&filename, true, &mut warnings).unwrap();
+        std::fs::write(&filename, "not json").unwrap();
+        assert!(parse_source_file(&filename, true, &mut Vec::new()).is_err());
+        let _ = std::fs::remove_file(&filename);
+        assert_eq!(servers.len(), 2);
+        assert_eq!(servers[0].args.command_line(), "-p 5201-5209 -c iperf.lab.example.com");
+        assert!(servers[1].ipv6_capable);
+        assert_eq!(warnings, vec!["Entry 3: no server address".to_string()]);
+    }
+
+    #[test]
+    fn offline_and_reachability() {
+        let mut server = ParsedServer { site: "Tokyo".to_string(), status: "Offline".to_string(), ..Default::default() };
+        assert!(is_offline(&server.status) && !is_offline("OK"));
+
+        let mut picker = ServerPicker::default();
+        assert!(!picker.matches(&server));
+        picker.show_offline = true;
+        assert!(picker.matches(&server));
+        picker.show_offline = false;
+        server.marker = "★".to_string();   // Favourites are always shown
+        assert!(picker.matches(&server));
+
+        let mut history = History::default();
+        assert_eq!(Reachability::default().indicator(), "");
+        history.record_test("tokyo.example.com", true);
+        history.record_test("tokyo.example.com", true);
+        assert_eq!(history.reachability["tokyo.example.com"].indicator(), "✓2/2");
+        history.record_test("tokyo.example.com", false);     // In the same second
+        let reachability = history.reachability["tokyo.example.com"].clone();
+        assert_eq!((reachability.worked, reachability.failed), (2, 1));
+        assert!(!reachability.last_failed.is_empty());
+        assert_eq!(reachability.indicator(), "✗2/3");
+        history.record_test("tokyo.example.com", true);
+        assert_eq!(history.reachability["tokyo.example.com"].indicator(), "✓3/4");
+        server.status = "OK".to_string();
+        server.reachability = history.reachability["tokyo.example.com"].clone();
+        assert_eq!(Column::Status.text(&server), "OK ✓3/4");
+    }
 }